@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::rc::Rc;
@@ -13,30 +13,101 @@ use crate::utils::{self, unwrap_or_return};
 
 use adw::prelude::AdwApplicationWindowExt;
 use adw::{self};
-use async_channel::{Receiver, Sender};
+use async_channel::Receiver;
 use cascade::cascade;
 use glib::{self};
-use gtk::{gio, Popover};
+use gtk::{gdk, gio, Popover};
 use gtk::{self, prelude::*};
 use layer_shell::{self, Edge, Layer, LayerShell};
-use crate::modules::{Module, ModuleType};
+use crate::config::BarConfig;
+use crate::modules::{Module, ModuleType, Notifications};
 
+/// labels of module buttons on a vertical bar are cut off after this
+const COMPACT_LABEL_CHARS: i32 = 4;
+
+#[derive(Debug, Clone, Copy)]
 pub enum Align {
     Start = 0,
     Center = 1,
     End = 2,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BarEdge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl BarEdge {
+    const ALL: [BarEdge; 4] = [BarEdge::Top, BarEdge::Bottom, BarEdge::Left, BarEdge::Right];
+
+    fn edge(self) -> Edge {
+        match self {
+            BarEdge::Top => Edge::Top,
+            BarEdge::Bottom => Edge::Bottom,
+            BarEdge::Left => Edge::Left,
+            BarEdge::Right => Edge::Right,
+        }
+    }
+    fn opposite(self) -> BarEdge {
+        match self {
+            BarEdge::Top => BarEdge::Bottom,
+            BarEdge::Bottom => BarEdge::Top,
+            BarEdge::Left => BarEdge::Right,
+            BarEdge::Right => BarEdge::Left,
+        }
+    }
+    fn orientation(self) -> gtk::Orientation {
+        match self {
+            BarEdge::Top | BarEdge::Bottom => gtk::Orientation::Horizontal,
+            BarEdge::Left | BarEdge::Right => gtk::Orientation::Vertical,
+        }
+    }
+    /// popovers open away from the edge
+    fn popover_direction(self) -> gtk::ArrowType {
+        match self {
+            BarEdge::Top => gtk::ArrowType::Down,
+            BarEdge::Bottom => gtk::ArrowType::Up,
+            BarEdge::Left => gtk::ArrowType::Right,
+            BarEdge::Right => gtk::ArrowType::Left,
+        }
+    }
+    /// the gap between the bar and its popovers
+    fn popover_offset(self) -> (i32, i32) {
+        match self {
+            BarEdge::Top => (0, 10),
+            BarEdge::Bottom => (0, -10),
+            BarEdge::Left => (10, 0),
+            BarEdge::Right => (-10, 0),
+        }
+    }
+    fn css_class(self) -> &'static str {
+        match self {
+            BarEdge::Top => "top",
+            BarEdge::Bottom => "bottom",
+            BarEdge::Left => "left",
+            BarEdge::Right => "right",
+        }
+    }
+}
+
 pub struct Bar {
     pub window: gtk::ApplicationWindow,
+    container: gtk::CenterBox,
     pub layout: (gtk::Box, gtk::Box, gtk::Box),
+    edge: Cell<BarEdge>,
     // pub modules: Modules,
-    pub s_ui: Sender<UIEvent>,
-    pub r_ui: Receiver<UIEvent>,
-    pub modules: RefCell<HashMap<ModuleType, Rc<dyn Module>>>,
+    pub modules: Rc<RefCell<HashMap<ModuleType, Rc<dyn Module>>>>,
 }
 impl Bar {
-    pub fn new(app: &gtk::Application, s_ui: Sender<UIEvent>, r_ui: Receiver<UIEvent>) -> Self {
+    pub fn new(
+        app: &gtk::Application,
+        monitor: &gdk::Monitor,
+        config: &BarConfig,
+    ) -> Self {
         let center_box = gtk::CenterBox::new();
         // let time_mod = TimeModule::new();
 
@@ -53,33 +124,26 @@ impl Bar {
         let window = cascade! {
         gtk::ApplicationWindow::new(app);
             ..init_layer_shell();
-            ..set_anchor(Edge::Top, true);
-            ..set_anchor(Edge::Right, true);
-            ..set_anchor(Edge::Left, true);
+            ..set_monitor(Some(monitor));
             ..auto_exclusive_zone_enable();
-            ..set_height_request(30);
+            ..add_css_class("bar");
             ..set_child(Some(&center_box));
         };
 
-        Self {
+        let bar = Self {
             window,
+            container: center_box,
             layout: (start, middle, end),
-            s_ui,
-            r_ui,
-            modules: RefCell::new(HashMap::new()),
-        }
+            edge: Cell::new(config.edge),
+            modules: Rc::new(RefCell::new(HashMap::new())),
+        };
+        bar.apply_config(config);
+        bar
     }
 
-    pub fn add_module<M>(&self, module: M, align: Align, add_widget: bool)
-    where 
-        M: Module + 'static,
-      {
-        let module = Rc::new(module);
-        let widget = create_module_container(module.clone());
-
-        self.modules
-            .borrow_mut()
-            .insert(module.get_type(), module);
+    pub fn add_module(&self, module: Rc<dyn Module>, align: Align, add_widget: bool) {
+        let widget = create_module_container(module.clone(), self.edge.get());
+        self.register_module(module);
         
         if !add_widget {
             return;
@@ -90,69 +154,144 @@ impl Bar {
             Align::End => self.layout.2.append(&widget),
         }    
     }
+    /// Removes every module, so the layout can be built again.
+    pub fn clear_modules(&self) {
+        for section in [&self.layout.0, &self.layout.1, &self.layout.2] {
+            while let Some(child) = section.first_child() {
+                section.remove(&child);
+            }
+        }
+        self.modules.borrow_mut().clear();
+    }
+    /// Modules added before keep the popover position of the old edge, the
+    /// layout is built again after this.
+    pub fn apply_config(&self, config: &BarConfig) {
+        let edge = config.edge;
+        self.edge.set(edge);
+        for side in BarEdge::ALL {
+            self.window.set_anchor(side.edge(), side != edge.opposite());
+            self.window.remove_css_class(side.css_class());
+        }
+        self.window.add_css_class(edge.css_class());
+
+        let orientation = edge.orientation();
+        self.container.set_orientation(orientation);
+        for section in [&self.layout.0, &self.layout.1, &self.layout.2] {
+            section.set_orientation(orientation);
+        }
+        match orientation {
+            gtk::Orientation::Vertical => self.window.set_size_request(config.size, -1),
+            _ => self.window.set_size_request(-1, config.size),
+        }
+    }
+    /// Modules inside of stacks are registered as well, so events find them.
+    fn register_module(&self, module: Rc<dyn Module>) {
+        for child in module.children() {
+            self.register_module(child);
+        }
+        self.modules.borrow_mut().insert(module.get_type(), module);
+    }
     pub fn get_module(&self, module_type: ModuleType) -> Option<Rc<dyn Module>> {
         self.modules.borrow().get(&module_type).cloned()
     }
-    pub fn event_loop(&mut self) {
-        let s_ui = self.s_ui.clone();
-        let r_ui = self.r_ui.clone();
+    pub fn show(&self) {
+        self.window.present()
+    }
 
-        let not_mod = self.get_module(ModuleType::Notifications);
+    pub fn destroy(&self) {
+        self.window.destroy()
+    }
+}
 
-        glib::MainContext::default().spawn_local(async move {
-            let not_mod = not_mod.clone();
-            while let Ok(event) = r_ui.recv().await {
-                match event {
-                    UIEvent::Notification(event) => {
-                        handle_not_event(event, not_mod.clone());
+/// Hands the events of the server to the notifications module. There is one
+/// loop for all bars, since they share the module.
+pub fn event_loop<F>(r_ui: Receiver<UIEvent>, notifications: F)
+where
+    F: Fn() -> Option<Notifications> + 'static,
+{
+    glib::MainContext::default().spawn_local(async move {
+        while let Ok(event) = r_ui.recv().await {
+            // looked up for every event, the layout can be rebuilt in between
+            let module = notifications();
+            match event {
+                UIEvent::Notification(event) => {
+                    handle_not_event(event, module.as_ref());
+                }
+                UIEvent::NameOwned(owned) => {
+                    if let Some(module) = module {
+                        module.set_name_owned(owned);
                     }
                 }
-            };
-        });
-    }
-
-    pub fn show(&self) {
-        self.window.present()
-    }
+            }
+        };
+    });
 }
 
 fn handle_not_event(
     event: NotificationEvent,
-    module: Option<Rc<dyn Module>>,
+    module: Option<&Notifications>,
 ) {
     let module = unwrap_or_return!(module, Option);
-    let module = unwrap_or_return!(
-        module.as_any().downcast_ref::<crate::modules::Notifications>(), Option
-    );
 
     match event {
         NotificationEvent::NewNotification(notification) => {
-            println!("New notification: {:?}", notification);
+            println!("New notification {} from {}", notification.id, notification.app_name);
             module.add_notification(notification);
         }
-        _ => {}
+        NotificationEvent::Replaced(notification) => {
+            println!("Replaced notification {} from {}", notification.id, notification.app_name);
+            module.replace_notification(notification);
+        }
+        NotificationEvent::Closed(id) => {
+            module.remove_notification(id);
+        }
     };
 }
 
-fn create_module_container<M>(module: Rc<M>) -> gtk::MenuButton
-where 
-    M: Module + 'static,
- {
+fn create_module_container(module: Rc<dyn Module>, edge: BarEdge) -> gtk::MenuButton {
     
-    let label = gtk::Label::new(Some(module.name()));
     let popover = Popover::new();
+    // modules can bring their own button, the name is used otherwise
+    let button = module.get_button().unwrap_or_else(|| {
+        let label = gtk::Label::new(Some(module.name()));
+        cascade! {
+            gtk::MenuButton::new();
+            ..set_child(Some(&label));
+        }
+    });
     
     popover.connect_realize(move |popover| {
         popover.set_has_arrow(false);
-        popover.set_offset(0, 10);
+        let (x, y) = edge.popover_offset();
+        popover.set_offset(x, y);
         popover.set_child(Some(&module.get_widget()));
     });
     
-    let button =cascade! {
-        gtk::MenuButton::new();
-        ..set_popover(Some(&popover));
-        ..set_child(Some(&label));
-    };
+    if edge.orientation() == gtk::Orientation::Vertical {
+        if let Some(content) = button.child() {
+            compact(&content);
+        }
+    }
+    button.add_css_class("module");
+    button.set_direction(edge.popover_direction());
+    button.set_popover(Some(&popover));
     button
 }
 
+/// Stacks the content of a module button and shortens its labels, so it fits
+/// into a bar on the left or right.
+fn compact(widget: &gtk::Widget) {
+    if let Some(content) = widget.downcast_ref::<gtk::Box>() {
+        content.set_orientation(gtk::Orientation::Vertical);
+        let mut child = content.first_child();
+        while let Some(widget) = child {
+            compact(&widget);
+            child = widget.next_sibling();
+        }
+    } else if let Some(label) = widget.downcast_ref::<gtk::Label>() {
+        label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+        label.set_max_width_chars(COMPACT_LABEL_CHARS);
+        label.set_tooltip_text(Some(&label.text()));
+    }
+}
+
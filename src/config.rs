@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::bar::BarEdge;
+use crate::expiry::DEFAULT_EXPIRE_TIMEOUT;
+use crate::notification_server::OwnershipPolicy;
+use crate::popups::Corner;
+use crate::rules::Rules;
+use crate::sound::SoundConfig;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// The bar layout and module options, read from `config.toml` in the config
+/// directory:
+///
+/// ```toml
+/// [bar]
+/// edge = "top"
+/// size = 30
+///
+/// [server]
+/// ownership = "replace"
+///
+/// [[start]]
+/// module = "time"
+///
+/// [[center]]
+/// module = "notifications"
+/// popup-corner = "bottom-right"
+/// dnd-schedule = "22:00-07:00"
+/// sound-normal = "message-new-instant"
+///
+/// [outputs.HDMI-A-1]
+/// disabled = true
+///
+/// [[outputs.DP-2.end]]
+/// module = "time"
+///
+/// [[rules]]
+/// app-name = "buildbot"
+/// mute-popup = true
+/// ```
+///
+/// `start`, `center` and `end` list the modules of each section, in order.
+/// `outputs` changes them for single monitors, by connector name. `rules`
+/// are described in `panel::rules::Rules`.
+///
+/// Changes to the file are picked up while the bar is running, except for the
+/// `[server]` section, which needs a restart.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(default)]
+    pub bar: BarConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub start: Vec<ModuleConfig>,
+    #[serde(default)]
+    pub center: Vec<ModuleConfig>,
+    #[serde(default)]
+    pub end: Vec<ModuleConfig>,
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputConfig>,
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+/// Used when there is no config file, the time and notifications centred.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bar: BarConfig::default(),
+            server: ServerConfig::default(),
+            start: Vec::new(),
+            center: vec![ModuleConfig::Stack(StackConfig {
+                orientation: Orientation::Horizontal,
+                modules: vec![
+                    ModuleConfig::Time(TimeConfig::default()),
+                    ModuleConfig::Notifications(NotificationsConfig::default()),
+                ],
+            })],
+            end: Vec::new(),
+            outputs: HashMap::new(),
+            rules: Rules::default(),
+        }
+    }
+}
+
+/// Sections that are left out are taken from the top level.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputConfig {
+    pub disabled: bool,
+    pub start: Option<Vec<ModuleConfig>>,
+    pub center: Option<Vec<ModuleConfig>>,
+    pub end: Option<Vec<ModuleConfig>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BarConfig {
+    pub edge: BarEdge,
+    /// the height, or the width on the left and right
+    #[serde(alias = "height")]
+    pub size: i32,
+}
+
+impl Default for BarConfig {
+    fn default() -> Self {
+        Self {
+            edge: BarEdge::Top,
+            size: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServerConfig {
+    pub ownership: OwnershipPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "module", rename_all = "kebab-case")]
+pub enum ModuleConfig {
+    Time(TimeConfig),
+    Notifications(NotificationsConfig),
+    Stack(StackConfig),
+}
+
+impl ModuleConfig {
+    fn has_notifications(&self) -> bool {
+        match self {
+            ModuleConfig::Notifications(_) => true,
+            ModuleConfig::Stack(stack) => stack.modules.iter().any(ModuleConfig::has_notifications),
+            ModuleConfig::Time(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeConfig {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StackConfig {
+    #[serde(default)]
+    pub orientation: Orientation,
+    pub modules: Vec<ModuleConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Orientation {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+impl From<Orientation> for gtk::Orientation {
+    fn from(orientation: Orientation) -> Self {
+        match orientation {
+            Orientation::Horizontal => gtk::Orientation::Horizontal,
+            Orientation::Vertical => gtk::Orientation::Vertical,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NotificationsConfig {
+    /// in milliseconds, for notifications that leave it to the server
+    pub default_timeout: u32,
+    pub popup_corner: Corner,
+    pub max_popups: usize,
+    /// quiet hours like "22:00-07:00"
+    #[serde(deserialize_with = "deserialize_schedule")]
+    pub dnd_schedule: Option<(u32, u32)>,
+    pub dnd_allow_critical: bool,
+    /// only until the mute toggle is used, its state is remembered
+    pub sound_muted: bool,
+    /// sound names from the sound theme, played when the client does not ask
+    /// for a sound. An empty name means silence.
+    pub sound_low: String,
+    pub sound_normal: String,
+    pub sound_critical: String,
+}
+
+impl NotificationsConfig {
+    pub fn sound_config(&self) -> SoundConfig {
+        let name = |name: &String| Some(name.clone()).filter(|name| !name.is_empty());
+        SoundConfig {
+            muted: self.sound_muted,
+            defaults: [
+                name(&self.sound_low),
+                name(&self.sound_normal),
+                name(&self.sound_critical),
+            ],
+        }
+    }
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            default_timeout: DEFAULT_EXPIRE_TIMEOUT,
+            popup_corner: Corner::TopRight,
+            max_popups: 3,
+            dnd_schedule: None,
+            dnd_allow_critical: true,
+            sound_muted: false,
+            sound_low: String::new(),
+            sound_normal: String::new(),
+            sound_critical: "dialog-warning".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            // toml errors already point at the line and column
+            ConfigError::Parse(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        glib::user_config_dir().join(crate::NAME).join(CONFIG_FILE)
+    }
+
+    /// A missing file is not an error, the default layout is used then.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::from_file(&path)
+    }
+
+    /// The start, center and end modules for a monitor, `None` if it should
+    /// not get a bar.
+    pub fn sections(&self, connector: Option<&str>) -> Option<[&[ModuleConfig]; 3]> {
+        let output = match connector.and_then(|connector| self.outputs.get(connector)) {
+            Some(output) => output,
+            None => return Some([&self.start, &self.center, &self.end]),
+        };
+        if output.disabled {
+            return None;
+        }
+        Some([
+            output.start.as_deref().unwrap_or(&self.start),
+            output.center.as_deref().unwrap_or(&self.center),
+            output.end.as_deref().unwrap_or(&self.end),
+        ])
+    }
+
+    /// Whether any bar shows notifications, the server is only needed then.
+    pub fn has_notifications(&self) -> bool {
+        let outputs = self
+            .outputs
+            .values()
+            .flat_map(|output| [&output.start, &output.center, &output.end])
+            .flatten();
+        [&self.start, &self.center, &self.end]
+            .into_iter()
+            .chain(outputs)
+            .flatten()
+            .any(ModuleConfig::has_notifications)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&data).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+}
+
+fn deserialize_schedule<'de, D>(deserializer: D) -> Result<Option<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let schedule = String::deserialize(deserializer)?;
+    let invalid = || {
+        serde::de::Error::custom(format!(
+            "invalid schedule \"{}\", expected something like \"22:00-07:00\"",
+            schedule
+        ))
+    };
+    let (start, end) = schedule.split_once('-').ok_or_else(invalid)?;
+    let start = parse_time(start).ok_or_else(invalid)?;
+    let end = parse_time(end).ok_or_else(invalid)?;
+    Ok(Some((start, end)))
+}
+
+/// "HH:MM" to minutes after midnight.
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
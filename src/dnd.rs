@@ -0,0 +1,169 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use glib::{self};
+
+use crate::hints::Urgency;
+use crate::utils::unwrap_or_return;
+
+const STATE_FILE: &str = "dnd.ini";
+const STATE_GROUP: &str = "dnd";
+/// How often the schedule is checked, in seconds.
+const SCHEDULE_INTERVAL: u32 = 30;
+
+type ChangedCallback = dyn Fn(&DoNotDisturb) + 'static;
+
+/// Returned by `connect_changed`, to disconnect the callback again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangedHandlerId(u64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct DndConfig {
+    /// start and end of the quiet hours, in minutes after midnight
+    pub schedule: Option<(u32, u32)>,
+    /// lets critical notifications pop up even while dnd is on
+    pub allow_critical: bool,
+}
+
+impl Default for DndConfig {
+    fn default() -> Self {
+        Self {
+            schedule: None,
+            allow_critical: true,
+        }
+    }
+}
+
+/// Do-not-disturb keeps popups from showing, notifications still end up in
+/// the history. It is either switched on by hand or by the schedule.
+pub struct DoNotDisturb {
+    enabled: Cell<bool>,
+    scheduled: Cell<bool>,
+    config: Cell<DndConfig>,
+    path: PathBuf,
+    callbacks: RefCell<Vec<(ChangedHandlerId, Box<ChangedCallback>)>>,
+    next_handler: Cell<u64>,
+}
+
+impl DoNotDisturb {
+    pub fn new(config: DndConfig) -> Rc<Self> {
+        let dnd = Rc::new(Self {
+            enabled: Cell::new(false),
+            scheduled: Cell::new(false),
+            config: Cell::new(config),
+            path: glib::user_state_dir().join(crate::NAME).join(STATE_FILE),
+            callbacks: RefCell::new(Vec::new()),
+            next_handler: Cell::new(0),
+        });
+        dnd.enabled.set(dnd.load());
+        dnd.update_schedule();
+
+        let weak = Rc::downgrade(&dnd);
+        glib::timeout_add_seconds_local(SCHEDULE_INTERVAL, move || match weak.upgrade() {
+            Some(dnd) => {
+                dnd.update_schedule();
+                glib::ControlFlow::Continue
+            }
+            None => glib::ControlFlow::Break,
+        });
+        dnd
+    }
+
+    pub fn set_config(&self, config: DndConfig) {
+        self.config.set(config);
+        self.update_schedule();
+    }
+
+    /// Whether dnd was switched on by hand, the schedule is not included.
+    pub fn enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.enabled.get() || self.scheduled.get()
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.replace(enabled) == enabled {
+            return;
+        }
+        self.save();
+        self.emit_changed();
+    }
+
+    pub fn toggle(&self) {
+        self.set_enabled(!self.enabled.get());
+    }
+
+    /// Whether a notification with this urgency may pop up right now.
+    pub fn allows(&self, urgency: Urgency) -> bool {
+        !self.is_active() || (self.config.get().allow_critical && urgency == Urgency::Critical)
+    }
+
+    pub fn connect_changed<F>(&self, callback: F) -> ChangedHandlerId
+    where
+        F: Fn(&DoNotDisturb) + 'static,
+    {
+        let id = ChangedHandlerId(self.next_handler.get());
+        self.next_handler.set(id.0 + 1);
+        self.callbacks.borrow_mut().push((id, Box::new(callback)));
+        id
+    }
+
+    pub fn disconnect(&self, id: ChangedHandlerId) {
+        self.callbacks.borrow_mut().retain(|(handler, _)| *handler != id);
+    }
+
+    fn emit_changed(&self) {
+        for (_, callback) in self.callbacks.borrow().iter() {
+            callback(self);
+        }
+    }
+
+    fn update_schedule(&self) {
+        let scheduled = match self.config.get().schedule {
+            Some((start, end)) => in_schedule(current_minute(), start, end),
+            None => false,
+        };
+        if self.scheduled.replace(scheduled) != scheduled {
+            self.emit_changed();
+        }
+    }
+
+    fn load(&self) -> bool {
+        let file = glib::KeyFile::new();
+        if file
+            .load_from_file(&self.path, glib::KeyFileFlags::NONE)
+            .is_err()
+        {
+            return false;
+        }
+        file.boolean(STATE_GROUP, "enabled").unwrap_or(false)
+    }
+
+    fn save(&self) {
+        let file = glib::KeyFile::new();
+        file.set_boolean(STATE_GROUP, "enabled", self.enabled.get());
+
+        if let Some(dir) = self.path.parent() {
+            unwrap_or_return!(std::fs::create_dir_all(dir), Result);
+        }
+        unwrap_or_return!(file.save_to_file(&self.path), Result);
+    }
+}
+
+fn current_minute() -> u32 {
+    glib::DateTime::now_local()
+        .map(|now| (now.hour() * 60 + now.minute()) as u32)
+        .unwrap_or(0)
+}
+
+/// The schedule may wrap around midnight, like 22:00 to 07:00.
+fn in_schedule(minute: u32, start: u32, end: u32) -> bool {
+    if start <= end {
+        minute >= start && minute < end
+    } else {
+        minute >= start || minute < end
+    }
+}
@@ -1,11 +1,25 @@
-use crate::{notification_server};
+use crate::notification_server::{self, CloseReason};
 
 #[derive(Debug)]
 pub enum UIEvent {
     Notification(NotificationEvent),
+    /// whether the server owns the notification bus name
+    NameOwned(bool),
 }
 
 #[derive(Debug)]
 pub enum NotificationEvent {
     NewNotification(notification_server::Notification),
+    Replaced(notification_server::Notification),
+    Closed(u32),
+}
+
+/// Events sent from the ui back to the notification server.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// id, action key and an optional activation token
+    ActionInvoked(u32, String, Option<String>),
+    /// id and the text of an inline reply
+    Replied(u32, String),
+    NotificationClosed(u32, CloseReason),
 }
\ No newline at end of file
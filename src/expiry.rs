@@ -0,0 +1,112 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use glib::{self};
+
+/// Used for notifications that leave the timeout up to the server.
+pub const DEFAULT_EXPIRE_TIMEOUT: u32 = 5000;
+
+type ExpiredCallback = dyn Fn(u32) + 'static;
+
+struct Timer {
+    remaining: Duration,
+    started: Instant,
+    // None while the timer is paused
+    source: Option<glib::SourceId>,
+}
+
+/// Keeps one timer per notification and calls back once it runs out.
+pub struct ExpiryScheduler {
+    default_timeout: Cell<u32>,
+    timers: RefCell<HashMap<u32, Timer>>,
+    on_expired: Box<ExpiredCallback>,
+}
+
+impl ExpiryScheduler {
+    pub fn new<F: Fn(u32) + 'static>(on_expired: F) -> Rc<Self> {
+        Rc::new(Self {
+            default_timeout: Cell::new(DEFAULT_EXPIRE_TIMEOUT),
+            timers: RefCell::new(HashMap::new()),
+            on_expired: Box::new(on_expired),
+        })
+    }
+
+    /// Timeout in milliseconds for notifications sent with an expire_timeout
+    /// of -1, 0 keeps them around until they are closed.
+    pub fn set_default_timeout(&self, timeout: u32) {
+        self.default_timeout.set(timeout);
+    }
+
+    /// Starts the timer for a notification, replacing any previous one.
+    pub fn schedule(self: &Rc<Self>, id: u32, expire_timeout: i32) {
+        self.cancel(id);
+
+        let timeout = match expire_timeout {
+            0 => return,
+            timeout if timeout < 0 => self.default_timeout.get(),
+            timeout => timeout as u32,
+        };
+        if timeout == 0 {
+            return;
+        }
+
+        let remaining = Duration::from_millis(timeout as u64);
+        let source = self.start(id, remaining);
+        self.timers.borrow_mut().insert(
+            id,
+            Timer {
+                remaining,
+                started: Instant::now(),
+                source: Some(source),
+            },
+        );
+    }
+
+    pub fn cancel(&self, id: u32) {
+        let timer = self.timers.borrow_mut().remove(&id);
+        if let Some(source) = timer.and_then(|timer| timer.source) {
+            source.remove();
+        }
+    }
+
+    /// Stops the clock, for example while the notification is hovered.
+    pub fn pause(&self, id: u32) {
+        let mut timers = self.timers.borrow_mut();
+        let timer = match timers.get_mut(&id) {
+            Some(timer) => timer,
+            None => return,
+        };
+        if let Some(source) = timer.source.take() {
+            source.remove();
+            timer.remaining = timer.remaining.saturating_sub(timer.started.elapsed());
+        }
+    }
+
+    pub fn resume(self: &Rc<Self>, id: u32) {
+        let remaining = match self.timers.borrow().get(&id) {
+            Some(timer) if timer.source.is_none() => timer.remaining,
+            _ => return,
+        };
+        let source = self.start(id, remaining);
+
+        if let Some(timer) = self.timers.borrow_mut().get_mut(&id) {
+            timer.started = Instant::now();
+            timer.source = Some(source);
+        }
+    }
+
+    fn start(self: &Rc<Self>, id: u32, timeout: Duration) -> glib::SourceId {
+        let scheduler: Weak<Self> = Rc::downgrade(self);
+        glib::timeout_add_local_once(timeout, move || {
+            let scheduler = match scheduler.upgrade() {
+                Some(scheduler) => scheduler,
+                None => return,
+            };
+            // the source is done at this point, so it must not be removed again
+            scheduler.timers.borrow_mut().remove(&id);
+            (scheduler.on_expired)(id);
+        })
+    }
+}
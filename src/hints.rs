@@ -0,0 +1,126 @@
+use glib::{self};
+
+const IMAGE_DATA_TYPE: &str = "(iiibiiay)";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Urgency {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    Critical = 2,
+}
+
+impl From<u8> for Urgency {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Urgency::Low,
+            2 => Urgency::Critical,
+            _ => Urgency::Normal,
+        }
+    }
+}
+
+impl std::str::FromStr for Urgency {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "critical" => Ok(Urgency::Critical),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Urgency {
+    pub fn css_class(self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// Raw pixels sent through the `image-data` hint.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+    pub width: i32,
+    pub height: i32,
+    pub rowstride: i32,
+    pub has_alpha: bool,
+    pub bits_per_sample: i32,
+    pub channels: i32,
+    pub data: glib::Bytes,
+}
+
+impl ImageData {
+    pub fn from_variant(variant: &glib::Variant) -> Option<Self> {
+        if variant.type_().as_str() != IMAGE_DATA_TYPE {
+            return None;
+        }
+        Some(Self {
+            width: variant.child_get(0),
+            height: variant.child_get(1),
+            rowstride: variant.child_get(2),
+            has_alpha: variant.child_get(3),
+            bits_per_sample: variant.child_get(4),
+            channels: variant.child_get(5),
+            data: variant.child_value(6).data_as_bytes(),
+        })
+    }
+}
+
+/// The standard hints from the spec, everything else stays in the raw dict.
+#[derive(Debug, Clone, Default)]
+pub struct Hints {
+    pub urgency: Urgency,
+    pub category: Option<String>,
+    pub desktop_entry: Option<String>,
+    pub transient: bool,
+    pub resident: bool,
+    pub suppress_sound: bool,
+    pub sound_file: Option<String>,
+    pub sound_name: Option<String>,
+    pub position: Option<(i32, i32)>,
+    pub image_data: Option<ImageData>,
+    pub image_path: Option<String>,
+    /// only kept for old clients, it comes after `app_icon`
+    pub icon_data: Option<ImageData>,
+}
+
+impl Hints {
+    pub fn parse(hints: &glib::VariantDict) -> Self {
+        let x = lookup::<i32>(hints, "x");
+        let y = lookup::<i32>(hints, "y");
+
+        Self {
+            urgency: lookup::<u8>(hints, "urgency")
+                .map(Urgency::from)
+                .unwrap_or_default(),
+            category: lookup(hints, "category"),
+            desktop_entry: lookup(hints, "desktop-entry"),
+            transient: lookup(hints, "transient").unwrap_or(false),
+            resident: lookup(hints, "resident").unwrap_or(false),
+            suppress_sound: lookup(hints, "suppress-sound").unwrap_or(false),
+            sound_file: lookup(hints, "sound-file"),
+            sound_name: lookup(hints, "sound-name"),
+            position: x.zip(y),
+            // older versions of the spec used different names for these
+            image_data: ["image-data", "image_data"]
+                .into_iter()
+                .filter_map(|key| hints.lookup_value(key, None))
+                .find_map(|value| ImageData::from_variant(&value)),
+            image_path: lookup(hints, "image-path").or_else(|| lookup(hints, "image_path")),
+            icon_data: hints
+                .lookup_value("icon_data", None)
+                .and_then(|value| ImageData::from_variant(&value)),
+        }
+    }
+}
+
+/// Hints with an unexpected type are treated as missing.
+fn lookup<T: glib::FromVariant>(hints: &glib::VariantDict, key: &str) -> Option<T> {
+    hints.lookup::<T>(key).ok().flatten()
+}
@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use glib::{self, ToVariant};
+
+use crate::hints::Hints;
+use crate::notification_server::Notification;
+use crate::utils::unwrap_or_return;
+
+const HISTORY_FILE: &str = "history.ini";
+
+pub const DEFAULT_MAX_COUNT: usize = 100;
+/// one week, in seconds
+pub const DEFAULT_MAX_AGE: i64 = 7 * 24 * 60 * 60;
+/// raw pixels are far too big to keep on disk, restored notifications fall
+/// back to `image-path` and `app_icon`
+const IMAGE_DATA_HINTS: &[&str] = &["image-data", "image_data", "icon_data"];
+
+/// Keeps notifications on disk, so they survive restarts of the bar. Hints
+/// and actions are stored in the text form of `glib::Variant`.
+pub struct History {
+    path: PathBuf,
+    max_count: usize,
+    /// in seconds
+    max_age: i64,
+}
+
+impl History {
+    pub fn new(max_count: usize, max_age: i64) -> Self {
+        Self {
+            path: glib::user_state_dir().join(crate::NAME).join(HISTORY_FILE),
+            max_count,
+            max_age,
+        }
+    }
+
+    pub fn load(&self) -> Vec<Notification> {
+        if !self.path.exists() {
+            return Vec::new();
+        }
+        let file = glib::KeyFile::new();
+        if let Err(err) = file.load_from_file(&self.path, glib::KeyFileFlags::NONE) {
+            println!("Error loading history {}: {}", self.path.display(), err);
+            return Vec::new();
+        }
+
+        let notifications = file
+            .groups()
+            .iter()
+            .filter_map(|group| read_notification(&file, group.as_str()))
+            .collect();
+        self.retain(notifications)
+    }
+
+    /// Notifications with the `transient` hint are never written to disk.
+    pub fn save(&self, notifications: Vec<Notification>) {
+        let notifications = notifications
+            .into_iter()
+            .filter(|n| !n.parsed_hints.transient)
+            .collect();
+
+        let file = glib::KeyFile::new();
+        for (index, n) in self.retain(notifications).iter().enumerate() {
+            write_notification(&file, &format!("notification-{}", index), n);
+        }
+
+        if let Some(dir) = self.path.parent() {
+            unwrap_or_return!(std::fs::create_dir_all(dir), Result);
+        }
+        unwrap_or_return!(file.save_to_file(&self.path), Result);
+    }
+
+    /// Drops everything that is too old and keeps only the newest entries.
+    fn retain(&self, mut notifications: Vec<Notification>) -> Vec<Notification> {
+        let oldest = glib::real_time() / 1_000_000 - self.max_age;
+        notifications.retain(|n| n.received >= oldest);
+
+        let overflow = notifications.len().saturating_sub(self.max_count);
+        notifications.sort_by_key(|n| n.received);
+        notifications.split_off(overflow)
+    }
+}
+
+fn write_notification(file: &glib::KeyFile, group: &str, n: &Notification) {
+    file.set_string(group, "app_name", &n.app_name);
+    file.set_string(group, "app_icon", &n.app_icon);
+    file.set_string(group, "summary", &n.summary);
+    file.set_string(group, "body", &n.body);
+    file.set_string(group, "actions", &n.actions.to_variant().print(false));
+    file.set_string(group, "hints", &saved_hints(&n.hints).print(true));
+    file.set_integer(group, "expire_timeout", n.expire_timeout);
+    file.set_int64(group, "received", n.received);
+}
+
+fn saved_hints(hints: &glib::Variant) -> glib::Variant {
+    let hints = glib::VariantDict::new(Some(hints));
+    for key in IMAGE_DATA_HINTS {
+        hints.remove(key);
+    }
+    hints.end()
+}
+
+/// Restored notifications get the id 0, the server does not know them anymore.
+fn read_notification(file: &glib::KeyFile, group: &str) -> Option<Notification> {
+    let string = |key| file.string(group, key).ok().map(|s| s.to_string());
+    let variant = |key, type_: &glib::VariantTy| {
+        glib::Variant::parse(Some(type_), &string(key)?).ok()
+    };
+
+    let hints = variant("hints", glib::VariantTy::VARDICT)?;
+    let actions = variant("actions", glib::VariantTy::STRING_ARRAY)?;
+
+    Some(Notification {
+        id: 0,
+        app_name: string("app_name")?,
+        replaces_id: 0,
+        app_icon: string("app_icon")?,
+        summary: string("summary")?,
+        body: string("body")?,
+        actions: actions.get()?,
+        parsed_hints: Hints::parse(&glib::VariantDict::new(Some(&hints))),
+        hints,
+        expire_timeout: file.integer(group, "expire_timeout").ok()?,
+        received: file.int64(group, "received").ok()?,
+        muted: false,
+    })
+}
@@ -0,0 +1,124 @@
+use glib::{self};
+use gtk::prelude::*;
+use gtk::{gdk, gio};
+
+use crate::hints::ImageData;
+
+/// Turns the raw pixels of the `image-data` hint into a texture.
+pub fn to_texture(image: &ImageData) -> Option<gdk::Texture> {
+    let format = match (image.channels, image.has_alpha) {
+        (4, true) => gdk::MemoryFormat::R8g8b8a8,
+        (3, false) => gdk::MemoryFormat::R8g8b8,
+        _ => return None,
+    };
+    if image.bits_per_sample != 8 || !fits(image) {
+        return None;
+    }
+
+    let texture = gdk::MemoryTexture::new(
+        image.width,
+        image.height,
+        format,
+        &image.data,
+        image.rowstride as usize,
+    );
+    Some(texture.upcast())
+}
+
+/// Whether the sizes describe the data, gdk aborts on anything else. They
+/// come from the client and may overflow.
+fn fits(image: &ImageData) -> bool {
+    if image.width <= 0 || image.height <= 0 || image.rowstride <= 0 || image.channels <= 0 {
+        return false;
+    }
+    let row = match (image.width as usize).checked_mul(image.channels as usize) {
+        Some(row) => row,
+        None => return false,
+    };
+    // the last row does not have to be padded up to the full rowstride
+    let needed = (image.rowstride as usize)
+        .checked_mul(image.height as usize - 1)
+        .and_then(|rows| rows.checked_add(row));
+    match needed {
+        Some(needed) => image.rowstride as usize >= row && image.data.len() >= needed,
+        None => false,
+    }
+}
+
+pub enum ImageSource {
+    Texture(gdk::Texture),
+    IconName(String),
+}
+
+/// Picks the image to show in the order given by the spec: `image-data`,
+/// then `image-path`, then `app_icon` and last the deprecated `icon_data`.
+pub fn resolve(
+    image_data: Option<&ImageData>,
+    image_path: Option<&str>,
+    app_icon: &str,
+    icon_data: Option<&ImageData>,
+) -> Option<ImageSource> {
+    if let Some(texture) = image_data.and_then(to_texture) {
+        return Some(ImageSource::Texture(texture));
+    }
+    [image_path.unwrap_or_default(), app_icon]
+        .into_iter()
+        .filter(|path| !path.is_empty())
+        .find_map(resolve_path)
+        .or_else(|| icon_data.and_then(to_texture).map(ImageSource::Texture))
+}
+
+/// Paths are either file uris, absolute paths or names from the icon theme.
+fn resolve_path(path: &str) -> Option<ImageSource> {
+    let file = if path.starts_with("file://") {
+        gio::File::for_uri(path)
+    } else if path.starts_with('/') {
+        gio::File::for_path(path)
+    } else {
+        return Some(ImageSource::IconName(path.to_string()));
+    };
+
+    match gdk::Texture::from_file(&file) {
+        Ok(texture) => Some(ImageSource::Texture(texture)),
+        Err(err) => {
+            println!("Error loading image {}: {}", path, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: i32, height: i32, rowstride: i32, len: usize) -> ImageData {
+        ImageData {
+            width,
+            height,
+            rowstride,
+            has_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            data: glib::Bytes::from_owned(vec![0u8; len]),
+        }
+    }
+
+    #[test]
+    fn sizes_match_the_data() {
+        assert!(fits(&image(2, 2, 8, 16)));
+        // the last row may be short of the rowstride
+        assert!(fits(&image(2, 2, 12, 20)));
+        assert!(!fits(&image(2, 2, 8, 15)));
+    }
+
+    #[test]
+    fn bad_sizes_are_rejected() {
+        // rows shorter than the pixels they hold, with enough data otherwise
+        assert!(!fits(&image(4, 2, 4, 64)));
+        assert!(to_texture(&image(4, 2, 4, 64)).is_none());
+        assert!(!fits(&image(2, 2, 0, 16)));
+        assert!(!fits(&image(2, 2, -8, 16)));
+        assert!(!fits(&image(0, 2, 8, 16)));
+        assert!(!fits(&image(i32::MAX, i32::MAX, i32::MAX, 16)));
+    }
+}
@@ -0,0 +1,10 @@
+//! The notification server and everything it needs. It does not depend on
+//! gtk, so it also runs in a plain `glib::MainLoop` without a display.
+pub mod events;
+pub mod hints;
+pub mod notification_server;
+pub mod rate_limit;
+pub mod rules;
+pub mod utils;
+
+pub const NAME: &str = "bar";
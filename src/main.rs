@@ -1,29 +1,232 @@
 mod bar;
-mod events;
+mod config;
+mod dnd;
+mod expiry;
+mod history;
+mod image;
+mod markup;
 mod notification;
-mod notification_server;
-mod utils;
+mod popups;
+mod reload;
+mod sound;
+mod style;
 mod modules;
 
+use panel::{events, hints, notification_server, rules, utils, NAME};
+
 use gtk::prelude::*;
 use async_channel::{self};
 use glib::{self};
-use gtk::{self, gio::prelude::ApplicationExt};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use gtk::{self, gdk, gio, gio::prelude::ApplicationExt};
 
 
 pub const ID: &str = "io.github.bodenlosus.panel";
 
-pub const NAME: &str = "bar";
+/// Creates the modules listed in the config. There is only one notifications
+/// module, every bar listing it shows the same notifications. It outlives a
+/// reload of the config, so the notifications do too.
+struct ModuleBuilder {
+    app: gtk::Application,
+    s_server: async_channel::Sender<events::ServerEvent>,
+    notifications: Option<modules::Notifications>,
+    popups: Option<Rc<popups::NotificationPopups>>,
+    // whether the options of the notifications module were applied since the
+    // config was loaded
+    notifications_configured: bool,
+    // whether the notifications module was built for the current bar
+    notifications_used: bool,
+}
 
-fn load_default_css() {
-    let provider = gtk::CssProvider::new();
-    provider.load_from_string(include_str!("style.css"));
+impl ModuleBuilder {
+    fn new(app: &gtk::Application, s_server: async_channel::Sender<events::ServerEvent>) -> Self {
+        Self {
+            app: app.clone(),
+            s_server,
+            notifications: None,
+            popups: None,
+            notifications_configured: false,
+            notifications_used: false,
+        }
+    }
 
-    gtk::style_context_add_provider_for_display(
-        &gtk::gdk::Display::default().expect("Could not connect to a display."),
-        &provider,
-        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-    );
+    /// Called before building the bars of a newly loaded config.
+    fn reset(&mut self) {
+        self.notifications_configured = false;
+    }
+
+    /// Adds the modules of every section to the bar.
+    fn build_layout(&mut self, bar: &bar::Bar, sections: [&[config::ModuleConfig]; 3]) {
+        self.notifications_used = false;
+        let [start, center, end] = sections;
+        for (modules, align) in [
+            (start, bar::Align::Start),
+            (center, bar::Align::Center),
+            (end, bar::Align::End),
+        ] {
+            for module in modules {
+                bar.add_module(self.build(module), align, true);
+            }
+        }
+    }
+
+    fn build(&mut self, config: &config::ModuleConfig) -> Rc<dyn modules::Module> {
+        match config {
+            config::ModuleConfig::Time(_) => Rc::new(modules::TimeModule::new()),
+            config::ModuleConfig::Stack(config) => {
+                let stack = modules::ModuleStack::new(config.orientation.into());
+                for module in &config.modules {
+                    stack.add_module(self.build(module));
+                }
+                Rc::new(stack)
+            }
+            config::ModuleConfig::Notifications(config) => {
+                if self.notifications_used {
+                    println!("The notifications module is used more than once, only the options of the first one apply");
+                }
+                let notifications = match &self.notifications {
+                    Some(notifications) => {
+                        if !self.notifications_configured {
+                            apply_notifications_config(notifications, config);
+                            if let Some(popups) = &self.popups {
+                                popups.set_config(popup_config(config));
+                            }
+                        }
+                        notifications.clone()
+                    }
+                    None => self.build_notifications(config),
+                };
+                self.notifications = Some(notifications.clone());
+                self.notifications_configured = true;
+                self.notifications_used = true;
+                Rc::new(notifications)
+            }
+        }
+    }
+
+    fn build_notifications(&mut self, config: &config::NotificationsConfig) -> modules::Notifications {
+        let notifications = modules::Notifications::new(self.s_server.clone());
+        apply_notifications_config(&notifications, config);
+        self.popups = Some(notifications.create_popups(&self.app, popup_config(config)));
+
+        let dnd = notifications.dnd();
+        let dnd_action = gtk::gio::SimpleAction::new("toggle-dnd", None);
+        dnd_action.connect_activate(move |_, _| dnd.toggle());
+        self.app.add_action(&dnd_action);
+
+        notifications
+    }
+}
+
+/// One bar per monitor, created and removed as monitors come and go.
+struct Bars {
+    app: gtk::Application,
+    config: RefCell<config::Config>,
+    builder: Rc<RefCell<ModuleBuilder>>,
+    bars: RefCell<Vec<(gdk::Monitor, bar::Bar)>>,
+    // keeps running while no monitor is connected
+    _hold: gio::ApplicationHoldGuard,
+}
+
+impl Bars {
+    fn new(app: &gtk::Application, config: config::Config, builder: Rc<RefCell<ModuleBuilder>>) -> Self {
+        Self {
+            app: app.clone(),
+            config: RefCell::new(config),
+            builder,
+            bars: RefCell::new(Vec::new()),
+            _hold: app.hold(),
+        }
+    }
+
+    /// Creates the bars of new monitors and removes those of unplugged ones.
+    fn sync(&self) {
+        let display = gdk::Display::default().expect("Could not connect to a display.");
+        let monitors: Vec<gdk::Monitor> = display
+            .monitors()
+            .iter::<gdk::Monitor>()
+            .filter_map(Result::ok)
+            .collect();
+
+        self.bars.borrow_mut().retain(|(monitor, bar)| {
+            let connected = monitors.contains(monitor);
+            if !connected {
+                bar.destroy();
+            }
+            connected
+        });
+
+        for monitor in monitors {
+            if self.bars.borrow().iter().any(|(known, _)| *known == monitor) {
+                continue;
+            }
+            if let Some(bar) = self.create_bar(&monitor) {
+                self.bars.borrow_mut().push((monitor, bar));
+            }
+        }
+    }
+
+    fn create_bar(&self, monitor: &gdk::Monitor) -> Option<bar::Bar> {
+        let config = self.config.borrow();
+        let sections = config.sections(monitor.connector().as_deref())?;
+        let bar = bar::Bar::new(&self.app, monitor, &config.bar);
+        self.builder.borrow_mut().build_layout(&bar, sections);
+        bar.show();
+        Some(bar)
+    }
+
+    /// Rebuilds every bar with the new config.
+    fn set_config(&self, config: config::Config) {
+        self.config.replace(config);
+        self.builder.borrow_mut().reset();
+        let config = self.config.borrow();
+        self.bars.borrow_mut().retain(|(monitor, bar)| {
+            let sections = match config.sections(monitor.connector().as_deref()) {
+                Some(sections) => sections,
+                None => {
+                    bar.destroy();
+                    return false;
+                }
+            };
+            bar.clear_modules();
+            bar.apply_config(&config.bar);
+            self.builder.borrow_mut().build_layout(bar, sections);
+            true
+        });
+        drop(config);
+        // monitors that were disabled before
+        self.sync();
+    }
+}
+
+/// Requests the bus name once. Without a notifications module nothing would
+/// show the notifications, so the server is only started for one and leaves
+/// the name to other daemons otherwise.
+fn start_server(server: &notification_server::NotificationServer, started: &Cell<bool>) {
+    if started.replace(true) {
+        return;
+    }
+    // requesting the name is asynchronous, the server runs on the main loop
+    if let Err(e) = server.connect_to_dbus() {
+        eprintln!("Error connecting to D-Bus: {e:?}");
+    }
+}
+
+fn popup_config(config: &config::NotificationsConfig) -> popups::PopupConfig {
+    popups::PopupConfig {
+        corner: config.popup_corner,
+        max_visible: config.max_popups,
+    }
+}
+
+fn apply_notifications_config(notifications: &modules::Notifications, config: &config::NotificationsConfig) {
+    notifications.set_default_timeout(config.default_timeout);
+    notifications.sound().set_config(config.sound_config());
+    notifications.dnd().set_config(dnd::DndConfig {
+        schedule: config.dnd_schedule,
+        allow_critical: config.dnd_allow_critical,
+    });
 }
 
 fn main() {
@@ -31,37 +234,108 @@ fn main() {
     glib::set_application_name(NAME);
 
     let app = gtk::Application::new(Some(ID), Default::default());
-    app.connect_startup(|_| {
-        load_default_css();
+    app.add_main_option(
+        "toggle-dnd",
+        glib::Char::from(b'd'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::None,
+        "Toggle do-not-disturb of the running bar",
+        None,
+    );
+    // forwards the toggle to the running instance instead of starting a new bar
+    app.connect_handle_local_options(|app, options| {
+        if !options.contains("toggle-dnd") {
+            return -1;
+        }
+        if let Err(err) = app.register(gtk::gio::Cancellable::NONE) {
+            eprintln!("Error registering application: {err}");
+            return 1;
+        }
+        if !app.is_remote() {
+            eprintln!("{NAME} is not running");
+            return 1;
+        }
+        app.activate_action("toggle-dnd", None);
+        0
+    });
+    app.connect_startup(|app| {
+        let style = Rc::new(style::Style::new());
+        style.load();
+        let monitor = reload::watch_file(
+            &style::Style::path(),
+            glib::clone!(#[strong] style, move || style.load_user()),
+        );
+        app.connect_shutdown(move |_| {
+            if let Some(monitor) = &monitor {
+                monitor.cancel();
+            }
+        });
     });
     
 
     app.connect_activate(|app| {
         let (s_ui, r_ui) = async_channel::unbounded::<events::UIEvent>();
+        let (s_server, r_server) = async_channel::unbounded::<events::ServerEvent>();
 
-        let mut bar = bar::Bar::new(app, s_ui.clone(), r_ui.clone());
+        // a broken config should not leave the user without a bar
+        let config = config::Config::load().unwrap_or_else(|err| {
+            eprintln!("Error loading config {err}");
+            config::Config::default()
+        });
 
-        let stack = modules::ModuleStack::new(gtk::Orientation::Horizontal);
-        let time_mod = modules::TimeModule::new();
-        stack.add_module(time_mod);
-        let notification_mod = modules::Notifications::new();
-        stack.add_module(notification_mod);
+        let ownership = config.server.ownership;
+        let has_notifications = config.has_notifications();
+        let rules = config.rules.clone();
+        let builder = Rc::new(RefCell::new(ModuleBuilder::new(app, s_server.clone())));
+        bar::event_loop(
+            r_ui.clone(),
+            glib::clone!(#[strong] builder, move || builder.borrow().notifications.clone()),
+        );
 
-        bar.add_module(stack, bar::Align::Center, false);
-        bar.add_module(time_mod, , add_widget);
-        bar.event_loop();
-        bar.show();
-
-        let not_server = async move {
-            let not_server = notification_server::NotificationServer::new(s_ui.clone());
-            if let Err(e) = not_server.connect_to_dbus() {
-                eprintln!("Error connecting to D-Bus: {e:?}");
-            }
-        };
-        //spawn the notification server in a seperate thread
+        let bars = Rc::new(Bars::new(app, config, builder));
+        bars.sync();
+        let display = gdk::Display::default().expect("Could not connect to a display.");
+        display.monitors().connect_items_changed(glib::clone!(
+            #[strong] bars,
+            move |_, _, _, _| bars.sync()
+        ));
 
-        glib::MainContext::default().spawn(not_server);
+        let mut not_server = notification_server::NotificationServer::new(s_ui.clone(), r_server.clone());
+        not_server.set_rules(rules);
+        not_server.set_ownership_policy(ownership);
+        let server_started = Rc::new(Cell::new(false));
+        if has_notifications {
+            start_server(&not_server, &server_started);
+        }
 
+        let monitor = reload::watch_file(
+            &config::Config::path(),
+            glib::clone!(#[strong] bars, #[strong] not_server, #[strong] server_started, move || {
+                let config = match config::Config::load() {
+                    Ok(config) => config,
+                    Err(err) => {
+                        eprintln!("Error reloading config {err}");
+                        return;
+                    }
+                };
+                // the bus name is only requested once
+                if config.server.ownership != ownership {
+                    println!("The [server] options only change after a restart");
+                }
+                not_server.set_rules(config.rules.clone());
+                if config.has_notifications() {
+                    start_server(&not_server, &server_started);
+                } else if server_started.get() {
+                    println!("The notification server keeps running until a restart");
+                }
+                bars.set_config(config);
+            }),
+        );
+        app.connect_shutdown(move |_| {
+            if let Some(monitor) = &monitor {
+                monitor.cancel();
+            }
+        });
     });
     app.run();
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+/// Turns the html subset allowed by the body-markup spec into pango markup.
+/// Supported tags are translated, everything else is escaped so that it shows
+/// up as plain text instead of breaking the label.
+pub fn to_pango(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    // tags that still have to be closed, so the result is always balanced
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut rest = body;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => match parse_tag(rest) {
+                Some((tag, len)) => {
+                    push_tag(&mut out, &mut open, tag);
+                    rest = &rest[len..];
+                }
+                None => {
+                    out.push_str("&lt;");
+                    rest = &rest[1..];
+                }
+            },
+            '&' => {
+                let len = entity_len(rest);
+                if len > 0 {
+                    out.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                } else {
+                    out.push_str("&amp;");
+                    rest = &rest[1..];
+                }
+            }
+            _ => {
+                push_escaped(&mut out, &rest[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    while let Some(name) = open.pop() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+enum Tag {
+    Open(&'static str),
+    Close(&'static str),
+    Link(String),
+    Image(Option<String>),
+}
+
+fn push_tag(out: &mut String, open: &mut Vec<&'static str>, tag: Tag) {
+    match tag {
+        Tag::Open(name) => {
+            out.push_str(&format!("<{}>", name));
+            open.push(name);
+        }
+        Tag::Link(href) => {
+            out.push_str("<a href=\"");
+            push_escaped(out, &href);
+            out.push_str("\">");
+            open.push("a");
+        }
+        Tag::Close(name) => {
+            // closing a tag that was never opened would be invalid markup
+            let position = match open.iter().rposition(|open| *open == name) {
+                Some(position) => position,
+                None => return,
+            };
+            for name in open.drain(position..).rev() {
+                out.push_str(&format!("</{}>", name));
+            }
+        }
+        // images are not rendered, their alt text is shown instead
+        Tag::Image(alt) => {
+            if let Some(alt) = alt {
+                push_escaped(out, &alt);
+            }
+        }
+    }
+}
+
+/// Parses a supported tag at the start of `input` and returns it together
+/// with the number of bytes it spans.
+fn parse_tag(input: &str) -> Option<(Tag, usize)> {
+    let end = tag_end(input)?;
+    let inner = &input[1..end];
+    let inner = inner.strip_suffix('/').unwrap_or(inner).trim_end();
+
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    // like in html, "a < b" is text and not the start of a tag
+    if !inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let (name, attributes) = match inner.find(char::is_whitespace) {
+        Some(split) => (&inner[..split], inner[split..].trim()),
+        None => (inner, ""),
+    };
+    let name = name.to_ascii_lowercase();
+
+    let tag = match (name.as_str(), closing) {
+        ("b", false) => Tag::Open("b"),
+        ("i", false) => Tag::Open("i"),
+        ("u", false) => Tag::Open("u"),
+        ("b", true) => Tag::Close("b"),
+        ("i", true) => Tag::Close("i"),
+        ("u", true) => Tag::Close("u"),
+        ("a", true) => Tag::Close("a"),
+        ("a", false) => Tag::Link(attribute(attributes, "href").filter(|href| allowed_link(href))?),
+        ("img", false) => Tag::Image(attribute(attributes, "alt")),
+        _ => return None,
+    };
+    Some((tag, end + 1))
+}
+
+/// Links are opened with the default handler, so only schemes that are safe
+/// to open from a click are allowed.
+fn allowed_link(href: &str) -> bool {
+    let scheme = match href.split_once(':') {
+        Some((scheme, _)) => scheme.to_ascii_lowercase(),
+        None => return false,
+    };
+    matches!(scheme.as_str(), "http" | "https" | "mailto")
+}
+
+/// The markup of `to_pango` without its links, pango itself does not know
+/// them, they are an extension of `gtk::Label`.
+pub fn without_links(markup: &str) -> String {
+    let mut out = String::with_capacity(markup.len());
+    let mut rest = markup;
+    while let Some(start) = rest.find("<a href=\"") {
+        out.push_str(&rest[..start]);
+        // the href is escaped, so the first quote ends it
+        rest = &rest[start + "<a href=\"".len()..];
+        match rest.find("\">") {
+            Some(end) => rest = &rest[end + 2..],
+            None => return out,
+        }
+    }
+    out.push_str(rest);
+    out.replace("</a>", "")
+}
+
+/// Position of the `>` closing the tag at the start of `input`, quoted
+/// attribute values may contain `>` themselves.
+fn tag_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in input.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            (None, '<') => return None,
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+/// Reads a quoted attribute value, entities in it are decoded.
+fn attribute(attributes: &str, key: &str) -> Option<String> {
+    let mut rest = attributes;
+    while !rest.is_empty() {
+        let split = rest.find('=')?;
+        let name = rest[..split].trim();
+        let value = rest[split + 1..].trim_start();
+
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        let close = value.find(quote)?;
+
+        if name.eq_ignore_ascii_case(key) {
+            return Some(decode_entities(&value[..close]));
+        }
+        rest = value[close + 1..].trim_start();
+    }
+    None
+}
+
+/// Length of the entity at the start of `input`, or 0 if there is none.
+fn entity_len(input: &str) -> usize {
+    let end = match input.find(';') {
+        Some(end) if end > 1 => end,
+        _ => return 0,
+    };
+    let name = &input[1..end];
+    let valid = match name.strip_prefix('#') {
+        Some(number) => {
+            // GMarkup only knows a lowercase x
+            let code = match number.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => number.parse::<u32>(),
+            };
+            // "+1" parses as a number as well, but is no entity
+            number.chars().all(|c| c.is_ascii_alphanumeric())
+                && code.is_ok_and(is_xml_char)
+        }
+        None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+    };
+    if valid { end + 1 } else { 0 }
+}
+
+/// Characters that may be referenced in markup, anything else makes pango
+/// reject the whole text.
+fn is_xml_char(code: u32) -> bool {
+    matches!(code, 0x9 | 0xA | 0xD | 0x20..=0xD7FF | 0xE000..=0xFFFD | 0x10000..=0x10FFFF)
+}
+
+fn decode_entities(input: &str) -> String {
+    input
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_pango, without_links};
+
+    #[test]
+    fn supported_tags_are_translated() {
+        assert_eq!(to_pango("<b>bold</b> <I>italic</I> <u>under</u>"), "<b>bold</b> <i>italic</i> <u>under</u>");
+        assert_eq!(to_pango("<a href=\"https://example.org\">link</a>"), "<a href=\"https://example.org\">link</a>");
+    }
+
+    #[test]
+    fn nesting_is_balanced() {
+        assert_eq!(to_pango("<b><i>text</b>"), "<b><i>text</i></b>");
+        assert_eq!(to_pango("<b><i>text</b>rest</i>"), "<b><i>text</i></b>rest");
+        assert_eq!(to_pango("<u>open"), "<u>open</u>");
+        assert_eq!(to_pango("</b>closed"), "closed");
+    }
+
+    #[test]
+    fn stray_characters_are_escaped() {
+        assert_eq!(to_pango("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+        assert_eq!(to_pango("1 <2> 3"), "1 &lt;2&gt; 3");
+        assert_eq!(to_pango("<b"), "&lt;b");
+        assert_eq!(to_pango("&amp; &#65; &#x41; &foo; &#0; &#x110000; &#+1;"), "&amp; &#65; &#x41; &amp;foo; &amp;#0; &amp;#x110000; &amp;#+1;");
+        assert_eq!(to_pango("&#X41;"), "&amp;#X41;");
+        assert_eq!(to_pango("\"quoted\" 'text'"), "&quot;quoted&quot; &apos;text&apos;");
+    }
+
+    #[test]
+    fn unsupported_tags_are_escaped() {
+        assert_eq!(to_pango("<script>x</script>"), "&lt;script&gt;x&lt;/script&gt;");
+        assert_eq!(
+            to_pango("<span foreground=\"red\">x</span>"),
+            "&lt;span foreground=&quot;red&quot;&gt;x&lt;/span&gt;"
+        );
+    }
+
+    #[test]
+    fn attributes_need_quotes() {
+        assert_eq!(to_pango("<a href='https://it\"s'>l</a>"), "<a href=\"https://it&quot;s\">l</a>");
+        assert_eq!(to_pango("<a href=\"https://x&quot;y\">l</a>"), "<a href=\"https://x&quot;y\">l</a>");
+        assert_eq!(to_pango("<a href=\"https://a>b\">l</a>"), "<a href=\"https://a&gt;b\">l</a>");
+        // without a quoted href the link is shown as text
+        assert_eq!(to_pango("<a href=https://x>l</a>"), "&lt;a href=https://x&gt;l");
+        assert_eq!(to_pango("<a>l</a>"), "&lt;a&gt;l");
+    }
+
+    #[test]
+    fn only_web_and_mail_links_are_kept() {
+        assert_eq!(to_pango("<a href=\"HTTPS://example.org\">l</a>"), "<a href=\"HTTPS://example.org\">l</a>");
+        assert_eq!(to_pango("<a href=\"mailto:me@example.org\">l</a>"), "<a href=\"mailto:me@example.org\">l</a>");
+        assert_eq!(
+            to_pango("<a href=\"file:///etc/passwd\">l</a>"),
+            "&lt;a href=&quot;file:///etc/passwd&quot;&gt;l"
+        );
+        assert_eq!(to_pango("<a href=\"steam://run/1\">l</a>"), "&lt;a href=&quot;steam://run/1&quot;&gt;l");
+        assert_eq!(to_pango("<a href=\"relative\">l</a>"), "&lt;a href=&quot;relative&quot;&gt;l");
+    }
+
+    #[test]
+    fn links_can_be_removed() {
+        let markup = to_pango("<b>see <a href=\"https://example.org/?a=1&amp;b=2\">this</a></b>");
+        assert_eq!(without_links(&markup), "<b>see this</b>");
+        assert_eq!(without_links("no links"), "no links");
+    }
+
+    #[test]
+    fn images_show_their_alt_text() {
+        assert_eq!(to_pango("<img src=\"x.png\" alt=\"a &amp; b\"/>"), "a &amp; b");
+        assert_eq!(to_pango("<img src=\"x.png\" alt=\"<b>hi</b>\">"), "&lt;b&gt;hi&lt;/b&gt;");
+        assert_eq!(to_pango("<img src=\"x.png\">"), "");
+    }
+}
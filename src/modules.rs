@@ -1,11 +1,25 @@
-use crate::notification_server::Notification;
+use crate::dnd::{DndConfig, DoNotDisturb};
+use crate::events::ServerEvent;
+use crate::expiry::ExpiryScheduler;
+use crate::hints::Urgency;
+use crate::history::{self, History};
+use crate::markup;
+use crate::notification_server::{CloseReason, Notification};
+use crate::popups::{NotificationPopups, PopupConfig};
+use crate::sound::{SoundConfig, SoundPlayer};
 use crate::{notification::NotificationObject, utils::unwrap_or_return};
+use async_channel::Sender;
 use cascade::cascade;
 use glib::object::{Cast, IsA};
 use gtk::prelude::*;
 use gtk::{self, gio};
 use std::any::Any;
-use std::{cell::RefCell, rc::Rc};
+use std::collections::HashSet;
+use std::time::Duration;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub enum ModuleType {
@@ -20,6 +34,10 @@ pub trait Module: Any {
     fn get_button(&self) -> Option<gtk::MenuButton> {
         None
     }
+    /// Modules shown inside of this one, like the ones of a stack.
+    fn children(&self) -> Vec<Rc<dyn Module>> {
+        Vec::new()
+    }
     fn get_type (&self) -> ModuleType;
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
@@ -43,11 +61,7 @@ impl ModuleStack {
         }
     }
 
-    pub fn add_module<M>(&self, module: M)
-    where
-        M: Module + 'static,
-    {
-        let module = Rc::new(module);
+    pub fn add_module(&self, module: Rc<dyn Module>) {
         self.modules.borrow_mut().push(module);
     }
 }
@@ -77,6 +91,9 @@ impl Module for ModuleStack {
         button.set_child(Some(&label));
         Some(button)
     }
+    fn children(&self) -> Vec<Rc<dyn Module>> {
+        self.modules.borrow().clone()
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -170,23 +187,268 @@ impl Module for TimeModule {
 
 #[derive(Clone)]
 pub struct Notifications {
-    widget: RefCell<Option<gtk::ListView>>,
+    widget: RefCell<Option<gtk::Box>>,
     store: gio::ListStore,
+    s_server: Sender<ServerEvent>,
+    expiry: Rc<ExpiryScheduler>,
+    dnd: Rc<DoNotDisturb>,
+    sound: Rc<SoundPlayer>,
+    /// group keys of the collapsed groups
+    collapsed: Rc<RefCell<HashSet<String>>>,
+    name_owned: Rc<Cell<bool>>,
+    /// shown while another daemon owns the notification bus name
+    name_lost_widgets: Rc<RefCell<Vec<glib::WeakRef<gtk::Widget>>>>,
 }
 
 impl Notifications {
-    pub fn new() -> Self {
+    pub fn new(s_server: Sender<ServerEvent>) -> Self {
         let store = gio::ListStore::new::<NotificationObject>();
 
+        // expired notifications stay in the list, they are only marked as such
+        let expiry = ExpiryScheduler::new(glib::clone!(
+            #[strong] store,
+            #[strong] s_server,
+            move |id| {
+                let position = store.find_with_equal_func(|obj| {
+                    obj.downcast_ref::<NotificationObject>()
+                        .is_some_and(|obj| obj.id() == id)
+                });
+                let notification = position
+                    .and_then(|position| store.item(position))
+                    .and_downcast::<NotificationObject>();
+                if let (Some(position), Some(notification)) = (position, notification) {
+                    notification.set_expired(true);
+                    // transient notifications are not kept in the history
+                    if notification.transient() {
+                        store.remove(position);
+                    }
+                }
+                let event = ServerEvent::NotificationClosed(id, CloseReason::Expired);
+                if let Err(err) = s_server.try_send(event) {
+                    println!("Error sending expiry: {}", err);
+                }
+            }
+        ));
+
+        let history = History::new(history::DEFAULT_MAX_COUNT, history::DEFAULT_MAX_AGE);
+        // restored notifications are only history, they never pop up again
+        for n in history.load() {
+            let notification = NotificationObject::new();
+            notification.set(n);
+            notification.set_expired(true);
+            store.append(&notification);
+        }
+        connect_history(&store, history);
+
         Self {
             widget: RefCell::new(None),
             store,
+            s_server,
+            expiry,
+            dnd: DoNotDisturb::new(DndConfig::default()),
+            sound: Rc::new(SoundPlayer::new(SoundConfig::default())),
+            collapsed: Rc::new(RefCell::new(HashSet::new())),
+            name_owned: Rc::new(Cell::new(true)),
+            name_lost_widgets: Rc::new(RefCell::new(Vec::new())),
         }
     }
+    pub fn dnd(&self) -> Rc<DoNotDisturb> {
+        self.dnd.clone()
+    }
+    pub fn sound(&self) -> Rc<SoundPlayer> {
+        self.sound.clone()
+    }
+    /// Shows incoming notifications as popups in their own window.
+    pub fn create_popups(
+        &self,
+        app: &gtk::Application,
+        config: PopupConfig,
+    ) -> Rc<NotificationPopups> {
+        NotificationPopups::new(
+            app,
+            config,
+            &self.store,
+            self.s_server.clone(),
+            self.expiry.clone(),
+            self.dnd.clone(),
+        )
+    }
+    pub fn set_name_owned(&self, owned: bool) {
+        self.name_owned.set(owned);
+        self.name_lost_widgets.borrow_mut().retain(|widget| match widget.upgrade() {
+            Some(widget) => {
+                widget.set_visible(!owned);
+                true
+            }
+            None => false,
+        });
+    }
+    fn track_name_lost(&self, widget: &impl IsA<gtk::Widget>) {
+        widget.set_visible(!self.name_owned.get());
+        self.name_lost_widgets
+            .borrow_mut()
+            .push(widget.upcast_ref::<gtk::Widget>().downgrade());
+    }
+    pub fn set_default_timeout(&self, timeout: u32) {
+        self.expiry.set_default_timeout(timeout);
+    }
     pub fn add_notification(&self, n: Notification) {
         let notification = NotificationObject::new();
+        self.schedule_expiry(&n);
+        // notifications kept quiet by dnd or a rule make no sound either
+        if !n.muted && self.dnd.allows(n.parsed_hints.urgency) {
+            self.sound.play(&n.parsed_hints);
+        }
         notification.set(n);
         self.store.append(&notification);
+        self.evict_overflow();
+    }
+    /// Keeps the store from growing without bounds, the oldest notifications
+    /// go first.
+    fn evict_overflow(&self) {
+        let overflow = self.store.n_items().saturating_sub(MAX_NOTIFICATIONS);
+        if overflow == 0 {
+            return;
+        }
+        let evicted: Vec<NotificationObject> = (0..overflow)
+            .filter_map(|position| self.store.item(position).and_downcast())
+            .collect();
+        self.store.splice(0, overflow, &[] as &[glib::Object]);
+
+        for notif in evicted.iter().filter(|notif| notif.id() != 0) {
+            self.expiry.cancel(notif.id());
+            let event = ServerEvent::NotificationClosed(notif.id(), CloseReason::Undefined);
+            if let Err(err) = self.s_server.try_send(event) {
+                println!("Error sending eviction: {}", err);
+            }
+        }
+    }
+    /// Builds the group headers, with a count, a toggle to collapse the group
+    /// and a button to dismiss everything in it.
+    fn create_header_factory(&self, filter: &gtk::CustomFilter) -> gtk::SignalListItemFactory {
+        let factory = gtk::SignalListItemFactory::new();
+
+        let store = self.store.clone();
+        let collapsed = self.collapsed.clone();
+        let expiry = self.expiry.clone();
+        let s_server = self.s_server.clone();
+        let filter = filter.clone();
+        factory.connect_setup(move |_, header| {
+            let header = unwrap_or_return!(header.downcast_ref::<gtk::ListHeader>(), Option);
+            let weak_header = header.downgrade();
+            let group_key = move || {
+                weak_header
+                    .upgrade()?
+                    .item()
+                    .and_downcast::<NotificationObject>()
+                    .map(|notif| notif.group_key())
+            };
+
+            let name = cascade! {
+                gtk::Label::new(None);
+                ..set_hexpand(true);
+                ..set_xalign(0.0);
+                ..set_ellipsize(gtk::pango::EllipsizeMode::End);
+            };
+            let count = cascade! {
+                gtk::Label::new(None);
+                ..set_css_classes(&["count"]);
+            };
+            let toggle = gtk::Button::from_icon_name("pan-down-symbolic");
+            toggle.connect_clicked(glib::clone!(
+                #[strong] collapsed,
+                #[strong] group_key,
+                #[weak] filter,
+                move |toggle| {
+                    let key = unwrap_or_return!(group_key(), Option);
+                    let is_collapsed = {
+                        let mut collapsed = collapsed.borrow_mut();
+                        !collapsed.remove(&key) && collapsed.insert(key)
+                    };
+                    toggle.set_icon_name(collapse_icon(is_collapsed));
+                    filter.changed(gtk::FilterChange::Different);
+                }
+            ));
+            let clear = cascade! {
+                gtk::Button::from_icon_name("edit-clear-all-symbolic");
+                ..set_tooltip_text(Some("Clear group"));
+            };
+            clear.connect_clicked(glib::clone!(
+                #[weak] store,
+                #[strong] expiry,
+                #[strong] s_server,
+                #[strong] group_key,
+                move |_| {
+                    let key = unwrap_or_return!(group_key(), Option);
+                    dismiss_where(&store, &expiry, &s_server, |notif| notif.group_key() == key);
+                }
+            ));
+
+            let child = cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 5);
+                ..set_css_classes(&["notification-group"]);
+                ..append(&name);
+                ..append(&count);
+                ..append(&toggle);
+                ..append(&clear);
+            };
+            header.set_child(Some(&child));
+        });
+
+        let store = self.store.clone();
+        let collapsed = self.collapsed.clone();
+        factory.connect_bind(move |_, header| {
+            let header = unwrap_or_return!(header.downcast_ref::<gtk::ListHeader>(), Option);
+            let notif = unwrap_or_return!(header.item().and_downcast::<NotificationObject>(), Option);
+            let child = unwrap_or_return!(header.child().and_downcast::<gtk::Box>(), Option);
+
+            let name = unwrap_or_return!(child.first_child().and_downcast::<gtk::Label>(), Option);
+            let count = unwrap_or_return!(name.next_sibling().and_downcast::<gtk::Label>(), Option);
+            let toggle = unwrap_or_return!(count.next_sibling().and_downcast::<gtk::Button>(), Option);
+
+            let key = notif.group_key();
+            name.set_label(&notif.app_name());
+            count.set_label(&group_size(&store, &key).to_string());
+            toggle.set_icon_name(collapse_icon(collapsed.borrow().contains(&key)));
+        });
+
+        factory
+    }
+    fn schedule_expiry(&self, n: &Notification) {
+        // critical notifications stay until the user deals with them
+        let timeout = match n.parsed_hints.urgency {
+            Urgency::Critical => 0,
+            _ => n.expire_timeout,
+        };
+        self.expiry.schedule(n.id, timeout);
+    }
+    fn find_notification(&self, id: u32) -> Option<u32> {
+        self.store.find_with_equal_func(|obj| {
+            obj.downcast_ref::<NotificationObject>()
+                .is_some_and(|obj| obj.id() == id)
+        })
+    }
+    pub fn replace_notification(&self, n: Notification) {
+        let position = self.find_notification(n.id);
+
+        // the original may already be gone, in that case show it as a new one
+        let position = match position {
+            Some(position) => position,
+            None => return self.add_notification(n),
+        };
+        let notification = unwrap_or_return!(
+            self.store.item(position).and_downcast::<NotificationObject>(),
+            Option
+        );
+        self.schedule_expiry(&n);
+        notification.set(n);
+        // rebind the row so the view picks up the new values
+        self.store.items_changed(position, 1, 1);
+    }
+    pub fn remove_notification(&self, id: u32) {
+        self.expiry.cancel(id);
+        let position = unwrap_or_return!(self.find_notification(id), Option);
+        self.store.remove(position);
     }
 }
 
@@ -202,63 +464,278 @@ impl Module for Notifications {
             return widget.clone().upcast::<gtk::Widget>();
         }
 
-        let selection_model = gtk::NoSelection::new(Some(self.store.clone()));
+        let store = self.store.clone();
+        let collapsed = self.collapsed.clone();
+        let filter = gtk::CustomFilter::new(glib::clone!(
+            #[weak] store,
+            #[strong] collapsed,
+            #[upgrade_or] false,
+            move |obj| {
+                let notif = match obj.downcast_ref::<NotificationObject>() {
+                    Some(notif) => notif,
+                    None => return false,
+                };
+                // transient notifications only show up as popups
+                if notif.transient() {
+                    return false;
+                }
+                // collapsed groups only keep their newest notification around
+                let key = notif.group_key();
+                !collapsed.borrow().contains(&key)
+                    || newest_in_group(&store, &key).as_ref() == Some(notif)
+            }
+        ));
+        // which notification is the newest changes with every new one. The
+        // list is built again on every reload, the handler goes with it.
+        let handler = self.store.connect_items_changed(glib::clone!(
+            #[weak] filter,
+            move |_, _, _, _| {
+                filter.changed(gtk::FilterChange::Different);
+            }
+        ));
+        let store = self.store.downgrade();
+        filter.add_weak_ref_notify_local(move || {
+            if let Some(store) = store.upgrade() {
+                store.disconnect(handler);
+            }
+        });
+        let history = gtk::FilterListModel::new(Some(self.store.clone()), Some(filter.clone()));
+
+        // newest first inside a group, groups are sorted by their key
+        let sorter = gtk::CustomSorter::new(|a, b| {
+            let received = |obj: &glib::Object| {
+                obj.downcast_ref::<NotificationObject>()
+                    .map(|notif| notif.received())
+            };
+            received(b).cmp(&received(a)).into()
+        });
+        let section_sorter = gtk::CustomSorter::new(|a, b| {
+            let key = |obj: &glib::Object| {
+                obj.downcast_ref::<NotificationObject>()
+                    .map(|notif| notif.group_key())
+            };
+            key(a).cmp(&key(b)).into()
+        });
+        let sorted = gtk::SortListModel::new(Some(history), Some(sorter));
+        sorted.set_section_sorter(Some(&section_sorter));
+
+        let selection_model = gtk::NoSelection::new(Some(sorted));
         let factory = gtk::SignalListItemFactory::new();
+        let header_factory = self.create_header_factory(&filter);
 
+        let store = self.store.clone();
+        let expiry = self.expiry.clone();
+        let s_server = self.s_server.clone();
         factory.connect_setup(move |_, item| {
-            if let Some(item) = item.downcast_ref::<gtk::ListItem>() {
-                item.set_child(Some(&create_notification_widget()));
+            let item = unwrap_or_return!(item.downcast_ref::<gtk::ListItem>(), Option);
+            let child = create_notification_widget();
+
+            let weak_item = item.downgrade();
+            let notification = move || {
+                weak_item.upgrade()?.item().and_downcast::<NotificationObject>()
             };
+            let dismiss = Rc::new(glib::clone!(
+                #[weak] store,
+                #[strong] expiry,
+                #[strong] s_server,
+                #[strong] notification,
+                move || {
+                    let notif = unwrap_or_return!(notification(), Option);
+                    dismiss_where(&store, &expiry, &s_server, |other| *other == notif);
+                }
+            ));
+
+            let close = cascade! {
+                gtk::Button::from_icon_name("window-close-symbolic");
+                ..set_valign(gtk::Align::Start);
+                ..set_tooltip_text(Some("Dismiss"));
+                ..set_css_classes(&["flat", "close"]);
+            };
+            close.connect_clicked(glib::clone!(
+                #[strong] dismiss,
+                move |_| dismiss()
+            ));
+            if let Some(content) = child.first_child().and_downcast::<gtk::Box>() {
+                content.append(&close);
+            }
+
+            // dragging a row sideways far enough dismisses it
+            let swipe = gtk::GestureDrag::new();
+            swipe.connect_drag_update(glib::clone!(
+                #[weak] child,
+                move |_, x, y| {
+                    set_swipe_offset(&child, if x.abs() > y.abs() { x } else { 0.0 });
+                }
+            ));
+            swipe.connect_drag_end(glib::clone!(
+                #[weak] child,
+                #[strong] dismiss,
+                move |_, x, y| {
+                    set_swipe_offset(&child, 0.0);
+                    if x.abs() > y.abs() && x.abs() > child.width() as f64 * SWIPE_THRESHOLD {
+                        dismiss();
+                    }
+                }
+            ));
+            child.add_controller(swipe);
+
+            // keep hovered notifications from expiring under the cursor
+            let hover = gtk::EventControllerMotion::new();
+            let hovered_id = |item: &glib::WeakRef<gtk::ListItem>| {
+                item.upgrade()?
+                    .item()
+                    .and_downcast::<NotificationObject>()
+                    .map(|notif| notif.id())
+            };
+            hover.connect_enter(glib::clone!(
+                #[weak(rename_to = expiry)] expiry,
+                #[strong(rename_to = item)] item.downgrade(),
+                move |_, _, _| {
+                    if let Some(id) = hovered_id(&item) {
+                        expiry.pause(id);
+                    }
+                }
+            ));
+            hover.connect_leave(glib::clone!(
+                #[weak(rename_to = expiry)] expiry,
+                #[strong(rename_to = item)] item.downgrade(),
+                move |_| {
+                    if let Some(id) = hovered_id(&item) {
+                        expiry.resume(id);
+                    }
+                }
+            ));
+            child.add_controller(hover);
+
+            keep_time_updated(&child, notification);
+
+            item.set_child(Some(&child));
         });
 
+        let s_server = self.s_server.clone();
         factory.connect_bind(move |_, item| {
             let (child, notif) = unwrap_or_return!(
                 downcast_list_item::<gtk::Box, NotificationObject>(item),
                 Option
             );
+            bind_notification_widget(&child, &notif, &s_server);
+        });
 
-            let header =
-                unwrap_or_return!(child.first_child().and_downcast::<gtk::Label>(), Option);
-            let body =
-                unwrap_or_return!(header.next_sibling().and_downcast::<gtk::Label>(), Option);
-            let time = unwrap_or_return!(body.next_sibling().and_downcast::<gtk::Label>(), Option);
+        let list = gtk::ListView::new(Some(selection_model), Some(factory));
+        list.set_header_factory(Some(&header_factory));
 
-            let name = notif.app_name();
-            let body_text = notif.body();
+        list.set_width_request(200);
+        list.set_css_classes(&["notification-list"]);
+        // clicking a row triggers the default action, if the client has one
+        list.set_single_click_activate(true);
 
-            cascade! {
-                header;
-                ..set_css_classes(&["header"]);
-                ..set_ellipsize(gtk::pango::EllipsizeMode::End);
-                ..set_label(&name);
-            };
-            cascade! {
-                body;
-                ..set_ellipsize(gtk::pango::EllipsizeMode::End);
-                ..set_label(&body_text);
-                ..set_css_classes(&["body"]);
-            };
+        let s_server = self.s_server.clone();
+        list.connect_activate(move |view, position| {
+            let notif = unwrap_or_return!(
+                view.model()
+                    .and_then(|model| model.item(position))
+                    .and_downcast::<NotificationObject>(),
+                Option
+            );
+            if notif.expired() {
+                return;
+            }
+            if notif.action_pairs().iter().any(|(key, _)| key == "default") {
+                invoke_action(view, &s_server, notif.id(), "default");
+            }
+        });
 
-            if let Ok(dt) = glib::DateTime::now_local() {
-                let time_str = dt.format("%H:%M:%S").unwrap_or_default();
-                cascade! {
-                    time;
-                    ..set_ellipsize(gtk::pango::EllipsizeMode::End);
-                    ..set_css_classes(&["time"]);
-                    ..set_label(&time_str);
-                };
+        let dnd_toggle = cascade! {
+            gtk::ToggleButton::new();
+            ..set_icon_name("notifications-disabled-symbolic");
+            ..set_tooltip_text(Some("Do not disturb"));
+            ..set_active(self.dnd.enabled());
+        };
+        let dnd = self.dnd.clone();
+        dnd_toggle.connect_toggled(move |toggle| {
+            dnd.set_enabled(toggle.is_active());
+        });
+        follow_dnd(&self.dnd, &dnd_toggle, |dnd_toggle, dnd| {
+            dnd_toggle.set_active(dnd.enabled());
+        });
+
+        let clear_all = gtk::Button::with_label("Clear all");
+        clear_all.connect_clicked(glib::clone!(
+            #[weak(rename_to = store)] self.store,
+            #[strong(rename_to = expiry)] self.expiry,
+            #[strong(rename_to = s_server)] self.s_server,
+            move |_| {
+                // transient notifications are not in the list, leave their popups alone
+                dismiss_where(&store, &expiry, &s_server, |notif| !notif.transient());
             }
+        ));
+
+        let mute_toggle = cascade! {
+            gtk::ToggleButton::new();
+            ..set_icon_name("audio-volume-muted-symbolic");
+            ..set_tooltip_text(Some("Mute sounds"));
+            ..set_active(self.sound.muted());
+        };
+        let sound = self.sound.clone();
+        mute_toggle.connect_toggled(move |toggle| {
+            sound.set_muted(toggle.is_active());
         });
 
-        let container = gtk::ListView::new(Some(selection_model), Some(factory));
+        let header = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 5);
+            ..set_halign(gtk::Align::End);
+            ..set_css_classes(&["notification-header"]);
+            ..append(&clear_all);
+            ..append(&mute_toggle);
+            ..append(&dnd_toggle);
+        };
 
-        container.set_width_request(200);
-        container.set_css_classes(&["notification-list"]);
+        let name_lost = cascade! {
+            gtk::Label::new(Some("Another notification daemon is running, notifications will show up once it exits"));
+            ..set_wrap(true);
+            ..set_max_width_chars(30);
+            ..set_css_classes(&["name-lost"]);
+        };
+        self.track_name_lost(&name_lost);
+
+        let container = cascade! {
+            gtk::Box::new(gtk::Orientation::Vertical, 5);
+            ..append(&name_lost);
+            ..append(&header);
+            ..append(&list);
+        };
 
         *widget = Some(container.clone());
 
         container.upcast::<gtk::Widget>()
     }
+    /// Shows icons next to the name while do-not-disturb is on, or while
+    /// another daemon receives the notifications.
+    fn get_button(&self) -> Option<gtk::MenuButton> {
+        let dnd_icon = cascade! {
+            gtk::Image::from_icon_name("notifications-disabled-symbolic");
+            ..set_visible(self.dnd.is_active());
+        };
+        follow_dnd(&self.dnd, &dnd_icon, |dnd_icon, dnd| {
+            dnd_icon.set_visible(dnd.is_active());
+        });
+
+        let name_lost_icon = cascade! {
+            gtk::Image::from_icon_name("dialog-warning-symbolic");
+            ..set_tooltip_text(Some("Not receiving notifications"));
+        };
+        self.track_name_lost(&name_lost_icon);
+
+        let content = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 5);
+            ..append(&gtk::Label::new(Some(self.name())));
+            ..append(&dnd_icon);
+            ..append(&name_lost_icon);
+        };
+        let button = gtk::MenuButton::new();
+        button.set_child(Some(&content));
+        Some(button)
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -273,20 +750,428 @@ impl Module for Notifications {
     }
 }
 
-fn create_notification_widget() -> gtk::Box {
+/// Writes the store back to disk shortly after it changed, so a burst of
+/// notifications only results in a single write.
+fn connect_history(store: &gio::ListStore, history: History) {
+    let history = Rc::new(history);
+    let pending = Rc::new(Cell::new(false));
+
+    store.connect_items_changed(move |store, _, _, _| {
+        if pending.replace(true) {
+            return;
+        }
+        glib::timeout_add_local_once(
+            Duration::from_secs(1),
+            glib::clone!(
+                #[strong] pending,
+                #[strong] history,
+                #[weak] store,
+                move || {
+                    pending.set(false);
+                    let notifications = store
+                        .iter::<NotificationObject>()
+                        .flatten()
+                        .map(|notification| notification.to_notification())
+                        .collect();
+                    history.save(notifications);
+                }
+            ),
+        );
+    });
+}
+
+/// The newest visible notification of a group, ties go to the later one in
+/// the store.
+fn newest_in_group(store: &gio::ListStore, key: &str) -> Option<NotificationObject> {
+    store
+        .iter::<NotificationObject>()
+        .flatten()
+        .filter(|notif| !notif.transient() && notif.group_key() == key)
+        .max_by_key(|notif| notif.received())
+}
+
+fn group_size(store: &gio::ListStore, key: &str) -> usize {
+    store
+        .iter::<NotificationObject>()
+        .flatten()
+        .filter(|notif| !notif.transient() && notif.group_key() == key)
+        .count()
+}
+
+fn collapse_icon(collapsed: bool) -> &'static str {
+    if collapsed {
+        "pan-end-symbolic"
+    } else {
+        "pan-down-symbolic"
+    }
+}
+
+/// Removes every matching notification and tells the clients they were
+/// dismissed by the user.
+fn dismiss_where<F>(
+    store: &gio::ListStore,
+    expiry: &ExpiryScheduler,
+    s_server: &Sender<ServerEvent>,
+    predicate: F,
+) where
+    F: Fn(&NotificationObject) -> bool,
+{
+    let mut dismissed = Vec::new();
+    store.retain(|obj| match obj.downcast_ref::<NotificationObject>() {
+        Some(notif) if predicate(notif) => {
+            dismissed.push(notif.id());
+            false
+        }
+        _ => true,
+    });
+
+    // restored notifications have no id, nobody is waiting for them
+    for id in dismissed.into_iter().filter(|id| *id != 0) {
+        expiry.cancel(id);
+        let event = ServerEvent::NotificationClosed(id, CloseReason::Dismissed);
+        if let Err(err) = s_server.try_send(event) {
+            println!("Error sending dismissal: {}", err);
+        }
+    }
+}
+
+/// Key of the kde style inline reply action.
+const REPLY_ACTION: &str = "inline-reply";
+
+/// Upper bound for the number of notifications kept in memory.
+const MAX_NOTIFICATIONS: u32 = 200;
+
+/// How far a row has to be dragged to be dismissed, relative to its width.
+const SWIPE_THRESHOLD: f64 = 0.4;
+
+/// Moves the row along with the drag and fades it out on the way.
+fn set_swipe_offset(child: &gtk::Box, offset: f64) {
+    child.set_margin_start(offset.max(0.0) as i32);
+    child.set_margin_end((-offset).max(0.0) as i32);
+
+    let width = child.width().max(1) as f64;
+    child.set_opacity(1.0 - (offset.abs() / width).min(1.0));
+}
+
+/// How often the relative receive times are brought up to date, in seconds.
+const TIME_REFRESH_INTERVAL: u32 = 30;
+
+/// The parts of a widget built by `create_notification_widget`.
+struct NotificationRow {
+    image: gtk::Image,
+    header: gtk::Label,
+    summary: gtk::Label,
+    body: gtk::Label,
+    time: gtk::Label,
+    actions: gtk::Box,
+    reply: gtk::Box,
+}
+
+impl NotificationRow {
+    fn from_widget(child: &gtk::Box) -> Option<Self> {
+        let content = child.first_child().and_downcast::<gtk::Box>()?;
+        let actions = content.next_sibling().and_downcast::<gtk::Box>()?;
+        let reply = actions.next_sibling().and_downcast::<gtk::Box>()?;
+        let image = content.first_child().and_downcast::<gtk::Image>()?;
+        let text = image.next_sibling().and_downcast::<gtk::Box>()?;
+        let header = text.first_child().and_downcast::<gtk::Label>()?;
+        let summary = header.next_sibling().and_downcast::<gtk::Label>()?;
+        let body = summary.next_sibling().and_downcast::<gtk::Label>()?;
+        let time = body.next_sibling().and_downcast::<gtk::Label>()?;
+        Some(Self {
+            image,
+            header,
+            summary,
+            body,
+            time,
+            actions,
+            reply,
+        })
+    }
+}
+
+/// Shows how long ago the notification arrived, the exact time goes into
+/// the tooltip.
+fn update_time_label(label: &gtk::Label, received: i64) {
+    let received = unwrap_or_return!(glib::DateTime::from_unix_local(received), Result);
+    let now = unwrap_or_return!(glib::DateTime::now_local(), Result);
+
+    let text = match now.to_unix() - received.to_unix() {
+        seconds if seconds < 60 => "just now".to_string(),
+        seconds if seconds < 60 * 60 => format!("{} min ago", seconds / 60),
+        seconds if seconds < 60 * 60 * 24 => format!("{} h ago", seconds / (60 * 60)),
+        _ => received.format("%x").map(|s| s.to_string()).unwrap_or_default(),
+    };
+    label.set_label(&text);
+    label.set_tooltip_text(received.format("%c").ok().as_deref());
+}
+
+/// Refreshes the time label of the widget until it is destroyed. The
+/// notification is looked up on every tick, since list rows get recycled.
+pub(crate) fn keep_time_updated<F>(widget: &gtk::Box, notification: F)
+where
+    F: Fn() -> Option<NotificationObject> + 'static,
+{
+    let widget = widget.downgrade();
+    glib::timeout_add_seconds_local(TIME_REFRESH_INTERVAL, move || {
+        let widget = match widget.upgrade() {
+            Some(widget) => widget,
+            None => return glib::ControlFlow::Break,
+        };
+        if let (Some(row), Some(notif)) = (NotificationRow::from_widget(&widget), notification()) {
+            update_time_label(&row.time, notif.received());
+        }
+        glib::ControlFlow::Continue
+    });
+}
+
+pub(crate) fn create_notification_widget() -> gtk::Box {
     let header_label = gtk::Label::new(None);
+    let summary_label = gtk::Label::new(None);
     let body_label = gtk::Label::new(None);
+    body_label.connect_activate_link(|label, uri| {
+        let window = label.root().and_downcast::<gtk::Window>();
+        gtk::UriLauncher::new(uri).launch(window.as_ref(), gio::Cancellable::NONE, |res| {
+            if let Err(err) = res {
+                println!("Error opening link: {}", err);
+            }
+        });
+        glib::Propagation::Stop
+    });
     let time_label = gtk::Label::new(None);
+    let actions = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        ..set_homogeneous(true);
+        ..set_css_classes(&["actions"]);
+    };
+    let reply = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        ..set_css_classes(&["reply"]);
+        ..set_visible(false);
+    };
 
-    cascade! {
+    let image = cascade! {
+        gtk::Image::new();
+        ..set_pixel_size(48);
+        ..set_valign(gtk::Align::Start);
+        ..set_css_classes(&["image"]);
+    };
+    let text = cascade! {
         gtk::Box::new(gtk::Orientation::Vertical, 5);
+        ..set_hexpand(true);
         ..append(&header_label);
+        ..append(&summary_label);
         ..append(&body_label);
         ..append(&time_label);
+    };
+    let content = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        ..append(&image);
+        ..append(&text);
+    };
+
+    cascade! {
+        gtk::Box::new(gtk::Orientation::Vertical, 5);
+        ..append(&content);
+        ..append(&actions);
+        ..append(&reply);
         ..set_css_classes(&["notification"]);
     }
 }
 
+pub(crate) fn bind_notification_widget(
+    child: &gtk::Box,
+    notif: &NotificationObject,
+    s_server: &Sender<ServerEvent>,
+) {
+    let NotificationRow {
+        image,
+        header,
+        summary,
+        body,
+        time,
+        actions,
+        reply,
+    } = unwrap_or_return!(NotificationRow::from_widget(child), Option);
+
+    child.set_css_classes(&["notification", notif.urgency_level().css_class()]);
+
+    if let Some(texture) = notif.image() {
+        image.set_paintable(Some(&texture));
+        image.set_visible(true);
+    } else if !notif.icon_name().is_empty() {
+        image.set_icon_name(Some(&notif.icon_name()));
+        image.set_visible(true);
+    } else {
+        image.clear();
+        image.set_visible(false);
+    }
+
+    let name = notif.app_name();
+    let body_text = notif.body();
+
+    cascade! {
+        header;
+        ..set_css_classes(&["header"]);
+        ..set_ellipsize(gtk::pango::EllipsizeMode::End);
+        ..set_label(&name);
+    };
+    // the summary is plain text, only the body may carry markup
+    let summary_text = notif.summary();
+    cascade! {
+        summary;
+        ..set_css_classes(&["summary"]);
+        ..set_ellipsize(gtk::pango::EllipsizeMode::End);
+        ..set_text(&summary_text);
+        ..set_visible(!summary_text.is_empty());
+    };
+    cascade! {
+        body;
+        ..set_ellipsize(gtk::pango::EllipsizeMode::End);
+        ..set_css_classes(&["body"]);
+    };
+    // a failed set_markup would keep the text of the previous notification,
+    // links are a gtk extension that pango cannot check
+    let body_markup = markup::to_pango(&body_text);
+    if gtk::pango::parse_markup(&markup::without_links(&body_markup), '\0').is_ok() {
+        body.set_markup(&body_markup);
+    } else {
+        body.set_text(&body_text);
+    }
+
+    cascade! {
+        time;
+        ..set_ellipsize(gtk::pango::EllipsizeMode::End);
+        ..set_css_classes(&["time"]);
+    };
+    update_time_label(&time, notif.received());
+
+    // rows get recycled, so drop the buttons of the previous notification
+    while let Some(button) = actions.first_child() {
+        actions.remove(&button);
+    }
+
+    let id = notif.id();
+    // expired notifications are closed for the client, their actions are gone
+    let action_pairs = if notif.expired() {
+        Vec::new()
+    } else {
+        notif.action_pairs()
+    };
+    bind_reply(&reply, notif, &action_pairs, s_server);
+
+    // the default action is invoked by clicking the notification itself,
+    // inline replies get their own entry
+    for (key, label) in action_pairs {
+        if key == "default" || key == REPLY_ACTION {
+            continue;
+        }
+        let s_server = s_server.clone();
+        let button = gtk::Button::with_label(&label);
+        button.connect_clicked(move |button| {
+            invoke_action(button, &s_server, id, &key);
+        });
+        actions.append(&button);
+    }
+    actions.set_visible(actions.first_child().is_some());
+}
+
+/// Calls `update` whenever do-not-disturb changes, for as long as the widget
+/// exists. Widgets are built again on every reload, so the callback is
+/// disconnected once the widget is gone.
+fn follow_dnd<W, F>(dnd: &Rc<DoNotDisturb>, widget: &W, update: F)
+where
+    W: IsA<glib::Object>,
+    F: Fn(&W, &DoNotDisturb) + 'static,
+{
+    let weak = widget.downgrade();
+    let handler = dnd.connect_changed(move |dnd| {
+        if let Some(widget) = weak.upgrade() {
+            update(&widget, dnd);
+        }
+    });
+    let dnd = Rc::downgrade(dnd);
+    widget.add_weak_ref_notify_local(move || {
+        if let Some(dnd) = dnd.upgrade() {
+            dnd.disconnect(handler);
+        }
+    });
+}
+
+/// Shows an entry for clients that offer an inline reply, the submitted text
+/// is sent back through the server.
+fn bind_reply(
+    reply: &gtk::Box,
+    notif: &NotificationObject,
+    action_pairs: &[(String, String)],
+    s_server: &Sender<ServerEvent>,
+) {
+    while let Some(child) = reply.first_child() {
+        reply.remove(&child);
+    }
+    let label = action_pairs
+        .iter()
+        .find(|(key, _)| key == REPLY_ACTION)
+        .map(|(_, label)| label.clone());
+    reply.set_visible(label.is_some());
+    let label = unwrap_or_return!(label, Option);
+
+    let hints = notif.hints();
+    let hint = |key| hints.lookup::<String>(key).ok().flatten();
+    let placeholder = hint("x-kde-reply-placeholder-text").unwrap_or(label);
+    let submit = hint("x-kde-reply-submit-button-text").unwrap_or_else(|| "Send".to_string());
+
+    let entry = cascade! {
+        gtk::Entry::new();
+        ..set_hexpand(true);
+        ..set_placeholder_text(Some(&placeholder));
+    };
+    let button = gtk::Button::with_label(&submit);
+
+    let id = notif.id();
+    let send = Rc::new(glib::clone!(
+        #[weak] entry,
+        #[strong] s_server,
+        move || {
+            let text = entry.text().to_string();
+            if text.is_empty() {
+                return;
+            }
+            entry.set_text("");
+            if let Err(err) = s_server.try_send(ServerEvent::Replied(id, text)) {
+                println!("Error sending reply: {}", err);
+            }
+        }
+    ));
+    entry.connect_activate(glib::clone!(
+        #[strong] send,
+        move |_| send()
+    ));
+    button.connect_clicked(move |_| send());
+
+    reply.append(&entry);
+    reply.append(&button);
+}
+
+pub(crate) fn invoke_action(
+    widget: &impl IsA<gtk::Widget>,
+    s_server: &Sender<ServerEvent>,
+    id: u32,
+    action: &str,
+) {
+    // lets the client raise its window when reacting to the action
+    let token = widget
+        .display()
+        .app_launch_context()
+        .startup_notify_id(None::<&gio::AppInfo>, &[])
+        .map(|token| token.to_string());
+
+    let event = ServerEvent::ActionInvoked(id, action.to_string(), token);
+    if let Err(err) = s_server.try_send(event) {
+        println!("Error sending action: {}", err);
+    }
+}
+
 fn downcast_list_item<T, U>(item: &glib::Object) -> Option<(T, U)>
 where
     T: IsA<gtk::Widget>,
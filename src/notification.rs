@@ -1,11 +1,16 @@
 
 
+use crate::hints::{Hints, Urgency};
+use crate::image::{self, ImageSource};
+use glib::subclass::prelude::*;
+use glib::ToVariant;
 use crate::notification_server::Notification;
 mod imp {
     use std::cell::{Cell, RefCell};
 
     use glib;
     use glib::subclass::types::ObjectSubclass;
+    use gtk::gdk;
     use gtk::prelude::*;
     use gtk::subclass::prelude::*;
 
@@ -30,6 +35,30 @@ mod imp {
         pub hints: RefCell<glib::VariantDict>,
         #[property(get, set)]
         pub expire_timeout: Cell<i32>,
+        #[property(get, set)]
+        pub expired: Cell<bool>,
+        /// unix timestamp of when the server received the notification
+        #[property(get, set)]
+        pub received: Cell<i64>,
+        #[property(get, set)]
+        pub urgency: Cell<u8>,
+        #[property(get, set)]
+        pub category: RefCell<String>,
+        #[property(get, set)]
+        pub desktop_entry: RefCell<String>,
+        #[property(get, set)]
+        pub transient: Cell<bool>,
+        #[property(get, set)]
+        pub resident: Cell<bool>,
+        #[property(get, set)]
+        pub suppress_sound: Cell<bool>,
+        /// kept out of the popups by a rule
+        #[property(get, set)]
+        pub muted: Cell<bool>,
+        // decoded once, so rows do not have to redo it on every bind
+        pub image: RefCell<Option<gdk::Texture>>,
+        #[property(get, set)]
+        pub icon_name: RefCell<String>,
     }
 
     #[glib::object_subclass]
@@ -54,6 +83,20 @@ impl NotificationObject {
     pub fn set(
         &self, n:Notification, 
     ) {
+        let image = image::resolve(
+            n.parsed_hints.image_data.as_ref(),
+            n.parsed_hints.image_path.as_deref(),
+            &n.app_icon,
+            n.parsed_hints.icon_data.as_ref(),
+        );
+        let (texture, icon_name) = match image {
+            Some(ImageSource::Texture(texture)) => (Some(texture), String::new()),
+            Some(ImageSource::IconName(name)) => (None, name),
+            None => (None, String::new()),
+        };
+        self.imp().image.replace(texture);
+        self.set_icon_name(icon_name);
+
         self.set_id(n.id);
         self.set_app_name(n.app_name);
         self.set_replaces_id(n.replaces_id);
@@ -63,6 +106,56 @@ impl NotificationObject {
         self.set_actions(n.actions);
         self.set_hints(glib::VariantDict::new(Some(&n.hints)));
         self.set_expire_timeout(n.expire_timeout);
-    }
+        self.set_expired(false);
+        self.set_received(n.received);
+        self.set_muted(n.muted);
 
+        let hints = n.parsed_hints;
+        self.set_urgency(hints.urgency as u8);
+        self.set_category(hints.category.unwrap_or_default());
+        self.set_desktop_entry(hints.desktop_entry.unwrap_or_default());
+        self.set_transient(hints.transient);
+        self.set_resident(hints.resident);
+        self.set_suppress_sound(hints.suppress_sound);
+    }
+    pub fn to_notification(&self) -> Notification {
+        let hints = self.hints().to_variant();
+        Notification {
+            id: self.id(),
+            app_name: self.app_name(),
+            replaces_id: self.replaces_id(),
+            app_icon: self.app_icon(),
+            summary: self.summary(),
+            body: self.body(),
+            actions: self.actions(),
+            parsed_hints: Hints::parse(&glib::VariantDict::new(Some(&hints))),
+            hints,
+            expire_timeout: self.expire_timeout(),
+            received: self.received(),
+            muted: self.muted(),
+        }
+    }
+    pub fn image(&self) -> Option<gtk::gdk::Texture> {
+        self.imp().image.borrow().clone()
+    }
+    pub fn urgency_level(&self) -> Urgency {
+        Urgency::from(self.urgency())
+    }
+    /// Actions come as a flat list of alternating keys and labels.
+    pub fn action_pairs(&self) -> Vec<(String, String)> {
+        self.actions()
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect()
+    }
+    /// Notifications are grouped by their desktop entry, or by the app name
+    /// if the client did not send one.
+    pub fn group_key(&self) -> String {
+        let desktop_entry = self.desktop_entry();
+        if desktop_entry.is_empty() {
+            self.app_name()
+        } else {
+            desktop_entry
+        }
+    }
 }
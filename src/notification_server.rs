@@ -1,20 +1,40 @@
-use async_channel::Sender;
+use async_channel::{Receiver, Sender};
 use glib::variant::ToVariant;
 use glib::{self};
-use gtk::gio;
-use std::{cell::RefCell, fmt::Debug, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
 
-use crate::events::{NotificationEvent, UIEvent};
-use crate::utils::unwrap_or_return;
+use crate::events::{NotificationEvent, ServerEvent, UIEvent};
+use crate::hints::Hints;
+use crate::rate_limit::{self, Decision, RateLimiter};
+use crate::rules::{self, Rules};
+use crate::utils::{self, unwrap_or_return};
 type NotificationCallback = dyn Fn(&Notification) + 'static;
 type NotificationClosedCallback = dyn Fn(u32) + 'static;
 type NotificationReplacedCallback = dyn Fn(u32, &Notification) + 'static;
 
-const NOTIFICATION_DBUS_NAME: &str = "org.freedesktop.Notifications";
-const NOTIFICATION_DBUS_PATH: &str = "/org/freedesktop/Notifications";
-const NOTIFICATION_DBUS_INTERFACE: &str = "org.freedesktop.Notifications";
+pub const NOTIFICATION_DBUS_NAME: &str = "org.freedesktop.Notifications";
+pub const NOTIFICATION_DBUS_PATH: &str = "/org/freedesktop/Notifications";
+pub const NOTIFICATION_DBUS_INTERFACE: &str = "org.freedesktop.Notifications";
 const NOTIFICATION_INTROSPECTION_XML: &str = include_str!("notifications-introspect.xml");
 
+/// Optional features from the spec, only the enabled ones are advertised
+/// through `GetCapabilities`.
+const CAPABILITIES: &[(&str, bool)] = &[
+    ("action-icons", false),
+    ("actions", true),
+    ("body", true),
+    ("body-hyperlinks", true),
+    ("body-images", false),
+    ("body-markup", true),
+    ("icon-multi", false),
+    ("icon-static", true),
+    // kde extension, clients send an "inline-reply" action and get the text
+    // back through NotificationReplied
+    ("inline-reply", true),
+    ("persistence", true),
+    ("sound", true),
+];
+
 #[derive(Clone)]
 pub struct Notification {
     pub id: u32,
@@ -25,7 +45,12 @@ pub struct Notification {
     pub body: String,
     pub actions: Vec<String>,
     pub hints: glib::Variant,
+    pub parsed_hints: Hints,
     pub expire_timeout: i32,
+    /// unix timestamp of when the notification arrived
+    pub received: i64,
+    /// kept out of the popups by a rule
+    pub muted: bool,
 }
 
 impl Debug for Notification {
@@ -38,7 +63,10 @@ impl Debug for Notification {
             .field("summary", &self.summary)
             .field("body", &self.body)
             .field("actions", &self.actions)
+            .field("parsed_hints", &self.parsed_hints)
             .field("expire_timeout", &self.expire_timeout)
+            .field("received", &self.received)
+            .field("muted", &self.muted)
             .finish()
     }
 }
@@ -46,75 +74,243 @@ impl Debug for Notification {
 #[derive(Clone)]
 pub struct NotificationServer {
     next_id: Rc<RefCell<u32>>,
+    // hints of every notification that is still open
+    active: Rc<RefCell<HashMap<u32, Hints>>>,
+    rules: Rc<RefCell<Rules>>,
+    rate_limiter: Rc<RefCell<RateLimiter>>,
+    policy: OwnershipPolicy,
     sender: Sender<UIEvent>,
+    receiver: Receiver<ServerEvent>,
 }
 
 impl NotificationServer {
-    pub fn new(sender: Sender<UIEvent>) -> Self {
+    pub fn new(sender: Sender<UIEvent>, receiver: Receiver<ServerEvent>) -> Self {
         NotificationServer {
             next_id: Rc::new(RefCell::new(1)),
+            active: Rc::new(RefCell::new(HashMap::new())),
+            rules: Rc::new(RefCell::new(Rules::default())),
+            rate_limiter: Rc::new(RefCell::new(RateLimiter::new(
+                rate_limit::DEFAULT_LIMIT,
+                rate_limit::DEFAULT_WINDOW,
+            ))),
+            policy: OwnershipPolicy::default(),
             sender,
+            receiver,
         }
     }
 
+    pub fn set_ownership_policy(&mut self, policy: OwnershipPolicy) {
+        self.policy = policy;
+    }
+
+    /// Clones of the server share the rules, so they can be swapped while it
+    /// is running.
+    pub fn set_rules(&self, rules: Rules) {
+        self.rules.replace(rules);
+    }
+
+    /// The name is never given up for good: while another daemon owns it the
+    /// server waits in the queue and takes over once that daemon exits.
     pub fn connect_to_dbus(&self) -> Result<(), glib::Error> {
-        let next_id_inner = self.next_id.clone();
-        let sender = self.sender.clone();
+        let server = self.clone();
+        let acquired_sender = self.sender.clone();
+        let lost_sender = self.sender.clone();
 
         let _ = gio::bus_own_name(
             gio::BusType::Session,
             NOTIFICATION_DBUS_NAME,
-            gio::BusNameOwnerFlags::NONE,
+            self.policy.flags(),
             move |bus_connection, _| {
-                bus_aquired(bus_connection, next_id_inner.clone(), sender.clone());
+                bus_aquired(bus_connection, server.clone());
             },
-            |x, y| {
-                println!("Name acquired {x:?} {y}");
+            move |_, name| {
+                println!("Name acquired {name}");
+                send_ownership(&acquired_sender, true);
             },
-            |x, y| {
-                println!("Name lost {x:?} {y}");
+            move |connection, name| {
+                match connection {
+                    Some(_) => println!("Name lost {name}, waiting for the current owner to exit"),
+                    None => println!("Name lost {name}, no connection to the session bus"),
+                }
+                send_ownership(&lost_sender, false);
             },
         );
         Ok(())
     }
+
+    /// Like `connect_to_dbus`, but on a connection that is already open, for
+    /// example one to a private bus.
+    pub fn own_name_on_connection(&self, connection: &gio::DBusConnection) {
+        bus_aquired(connection.clone(), self.clone());
+
+        let acquired_sender = self.sender.clone();
+        let lost_sender = self.sender.clone();
+        let _ = gio::bus_own_name_on_connection(
+            connection,
+            NOTIFICATION_DBUS_NAME,
+            self.policy.flags(),
+            move |_, name| {
+                println!("Name acquired {name}");
+                send_ownership(&acquired_sender, true);
+            },
+            move |_, name| {
+                println!("Name lost {name}, waiting for the current owner to exit");
+                send_ownership(&lost_sender, false);
+            },
+        );
+    }
 }
 
-fn bus_aquired(
-    connection: gio::DBusConnection,
-    next_id: Rc<RefCell<u32>>,
-    sender: Sender<UIEvent>,
+/// How to deal with another notification daemon that owns the bus name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OwnershipPolicy {
+    /// take the name, if the current owner allows it
+    Replace,
+    /// take the name if it is free, but hand it over to whoever asks for it
+    AllowReplacement,
+    /// wait until the current owner is gone
+    #[default]
+    Queue,
+}
+
+impl OwnershipPolicy {
+    fn flags(self) -> gio::BusNameOwnerFlags {
+        match self {
+            OwnershipPolicy::Replace => gio::BusNameOwnerFlags::REPLACE,
+            OwnershipPolicy::AllowReplacement => gio::BusNameOwnerFlags::ALLOW_REPLACEMENT,
+            OwnershipPolicy::Queue => gio::BusNameOwnerFlags::NONE,
+        }
+    }
+}
+
+fn send_ownership(sender: &Sender<UIEvent>, owned: bool) {
+    if let Err(err) = sender.try_send(UIEvent::NameOwned(owned)) {
+        println!("Error sending name ownership: {}", err);
+    }
+}
+
+/// Reason codes sent along with the `NotificationClosed` signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Expired = 1,
+    Dismissed = 2,
+    Closed = 3,
+    Undefined = 4,
+}
+
+/// Forgets about the notification and tells clients it went away. Does nothing
+/// if the notification was already closed.
+fn close_notification(
+    connection: &gio::DBusConnection,
+    server: &NotificationServer,
+    id: u32,
+    reason: CloseReason,
+) -> bool {
+    if server.active.borrow_mut().remove(&id).is_none() {
+        return false;
+    }
+
+    emit_signal(connection, "NotificationClosed", (id, reason as u32).to_variant());
+    true
+}
+
+fn emit_signal(connection: &gio::DBusConnection, signal_name: &str, params: glib::Variant) {
+    if let Err(err) = connection.emit_signal(
+        None,
+        NOTIFICATION_DBUS_PATH,
+        NOTIFICATION_DBUS_INTERFACE,
+        signal_name,
+        Some(&params),
+    ) {
+        println!("Error emitting {}: {}", signal_name, err);
+    }
+}
+
+/// Handles requests coming back from the ui, like invoked actions.
+async fn handle_server_events(connection: gio::DBusConnection, server: NotificationServer) {
+    while let Ok(event) = server.receiver.recv().await {
+        match event {
+            ServerEvent::ActionInvoked(id, action, token) => {
+                let resident = match server.active.borrow().get(&id) {
+                    Some(hints) => hints.resident,
+                    None => continue,
+                };
+                // the token has to arrive before the action so the client can
+                // use it when it reacts to the action
+                if let Some(token) = token {
+                    emit_signal(&connection, "ActivationToken", (id, token).to_variant());
+                }
+                emit_signal(&connection, "ActionInvoked", (id, action).to_variant());
+                close_after_interaction(&connection, &server, id, resident).await;
+            }
+            ServerEvent::Replied(id, text) => {
+                let resident = match server.active.borrow().get(&id) {
+                    Some(hints) => hints.resident,
+                    None => continue,
+                };
+                emit_signal(&connection, "NotificationReplied", (id, text).to_variant());
+                close_after_interaction(&connection, &server, id, resident).await;
+            }
+            // the ui already took care of its side, clients still need to know
+            ServerEvent::NotificationClosed(id, reason) => {
+                close_notification(&connection, &server, id, reason);
+            }
+        }
+    }
+}
+
+/// Closes a notification the user reacted to, resident notifications stay
+/// around until they are closed explicitly.
+async fn close_after_interaction(
+    connection: &gio::DBusConnection,
+    server: &NotificationServer,
+    id: u32,
+    resident: bool,
 ) {
+    if resident || !close_notification(connection, server, id, CloseReason::Dismissed) {
+        return;
+    }
+    let event = UIEvent::Notification(NotificationEvent::Closed(id));
+    if let Err(err) = server.sender.send(event).await {
+        println!("Error sending notification: {}", err);
+    }
+}
+
+fn bus_aquired(connection: gio::DBusConnection, server: NotificationServer) {
     let node_info = unwrap_or_return!(
         gio::DBusNodeInfo::for_xml(NOTIFICATION_INTROSPECTION_XML),
         Result
     );
     let interface_info = unwrap_or_return!(node_info.interfaces().first(), Option);
 
+    utils::spawn(handle_server_events(connection.clone(), server.clone()));
+
     let res = connection
         .register_object(NOTIFICATION_DBUS_PATH, interface_info)
         .method_call(
-            move |_connection,
-                  _sender ,
+            move |connection,
+                  sender,
                   _object_path ,
                   _interface_name,
                   method_name,
                   parameters,
                   invocation| {
-                    let next_id = next_id.clone();
-                    let sender = sender.clone();
+                    let server = server.clone();
+                    // peer-to-peer connections have no sender
+                    let sender = sender.unwrap_or_default().to_string();
                     let method_name = method_name.to_string();
                     let fut = handle_method_call(
-                            // _connection,
-                            // _sender,
+                            connection,
+                            sender,
                             // _object_path,
                             // _interface_name,
                             method_name,
                             parameters,
                             invocation,
-                            next_id,
-                            sender,
+                            server,
                         );
-                    let _ = glib::MainContext::default().spawn_local(fut);
+                    utils::spawn(fut);
                 }
         ,
         )   
@@ -128,32 +324,116 @@ fn bus_aquired(
     };
 }
 
+fn next_id(server: &NotificationServer) -> u32 {
+    let mut id = server.next_id.borrow_mut();
+    let current = *id;
+    *id = current.wrapping_add(1);
+    current
+}
+
+/// Closes a notification that is never shown right away, so the client does
+/// not wait for it. A replaced notification disappears from the ui as well.
+async fn discard_notification(
+    connection: &gio::DBusConnection,
+    server: &NotificationServer,
+    id: u32,
+    replaces: bool,
+) {
+    server.active.borrow_mut().remove(&id);
+    emit_signal(
+        connection,
+        "NotificationClosed",
+        (id, CloseReason::Undefined as u32).to_variant(),
+    );
+    if !replaces {
+        return;
+    }
+    let event = UIEvent::Notification(NotificationEvent::Closed(id));
+    if let Err(err) = server.sender.send(event).await {
+        println!("Error sending notification: {}", err);
+    }
+}
+
+/// Shows a single "N more from X" entry for everything a sender got
+/// suppressed for, it is updated in place as more notifications come in.
+async fn send_summary(
+    server: &NotificationServer,
+    sender: &str,
+    app_name: &str,
+    app_icon: &str,
+    suppressed: u32,
+    summary_id: Option<u32>,
+) {
+    let id = summary_id.unwrap_or_else(|| next_id(server));
+    server.rate_limiter.borrow_mut().set_summary_id(sender, id);
+
+    let name = if app_name.is_empty() { sender } else { app_name };
+    let notification = Notification {
+        id,
+        app_name: app_name.to_string(),
+        replaces_id: summary_id.unwrap_or(0),
+        app_icon: app_icon.to_string(),
+        summary: format!("{} more from {}", suppressed, name),
+        body: String::new(),
+        actions: Vec::new(),
+        hints: glib::VariantDict::new(None).end(),
+        parsed_hints: Hints::default(),
+        expire_timeout: -1,
+        received: glib::real_time() / 1_000_000,
+        muted: false,
+    };
+
+    let event = match summary_id {
+        Some(_) => UIEvent::Notification(NotificationEvent::Replaced(notification)),
+        None => UIEvent::Notification(NotificationEvent::NewNotification(notification)),
+    };
+    if let Err(err) = server.sender.send(event).await {
+        println!("Error sending notification: {}", err);
+    }
+}
+
+/// Runs the command of a rule, without waiting for it to finish.
+fn run_command(command: &str, n: &Notification) {
+    let launcher = gio::SubprocessLauncher::new(gio::SubprocessFlags::NONE);
+    for (key, value) in rules::command_env(n) {
+        launcher.setenv(key, value, true);
+    }
+    let argv = ["sh", "-c", command].map(std::ffi::OsStr::new);
+    if let Err(err) = launcher.spawn(&argv) {
+        println!("Error running {}: {}", command, err);
+    }
+}
+
 async fn handle_method_call(
-    // _connection: gio::DBusConnection,
-    // _sender: Option<&str>,
+    connection: gio::DBusConnection,
+    sender: String,
     // _object_path: &str,
     // _interface_name: Option<&str>,
     method_name: String,
     parameters: glib::Variant,
     invocation: gio::DBusMethodInvocation,
-    next_id: Rc<RefCell<u32>>,
-    sender: Sender<UIEvent>,
+    server: NotificationServer,
 ) {
     match method_name.as_str() {
         "GetServerInformation" => {
             let server_info = glib::Variant::tuple_from_iter([
-                glib::Variant::from("My Rust Server"), // Server name
-                glib::Variant::from("Rust GTK"),       // Vendor
-                glib::Variant::from("1.0"),            // Version
-                glib::Variant::from("1.2"),            // Spec version
+                glib::Variant::from(env!("CARGO_PKG_NAME")),    // Server name
+                glib::Variant::from("bodenlosus"),              // Vendor
+                glib::Variant::from(env!("CARGO_PKG_VERSION")), // Version
+                glib::Variant::from("1.2"),                     // Spec version
             ]);
             invocation.return_value(Some(&server_info));
         }
+        "GetCapabilities" => {
+            let capabilities: Vec<&str> = CAPABILITIES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| *name)
+                .collect();
+            let invoc_return = glib::Variant::tuple_from_iter([capabilities.to_variant()]);
+            invocation.return_value(Some(&invoc_return));
+        }
         "Notify" => {
-
-
-            println!("Unwrap mistt");
-
             let app_name = parameters.child_get::<String>(0);
             let replaces_id = parameters.child_get::<u32>(1);
             let app_icon = parameters.child_get::<String>(2);
@@ -165,18 +445,37 @@ async fn handle_method_call(
 
             let expire_timeout = parameters.child_get::<i32>(7);
 
+            let parsed_hints = Hints::parse(&hints);
             let hints = hints.to_variant();
 
-            let current_id = if replaces_id == 0 {
-                let mut id = next_id.borrow_mut();
-                let current = *id;
-                *id = current.wrapping_add(1);
-                current
-            } else {
+            // only ids handed out by this server can be replaced, anything else
+            // is treated like a fresh notification and gets a new id
+            let replaces = replaces_id != 0 && replaces_id < *server.next_id.borrow();
+
+            let current_id = if replaces {
                 replaces_id
+            } else {
+                next_id(&server)
             };
+            let received = glib::real_time() / 1_000_000;
+
+            let invoc_return = glib::Variant::tuple_from_iter(&[glib::Variant::from(current_id)]);
+            invocation.return_value(Some(&invoc_return));
+
+            // limits are per connection, keyed on the unique bus name of the
+            // sender. Peers without a name share one limit, so opening more
+            // connections does not get around it. Updates in place are not counted, progress bars and osds
+            // update that fast on purpose and must not lose their entry.
+            if !replaces {
+                let decision = server.rate_limiter.borrow_mut().check(&sender, received);
+                if let Decision::Suppress { suppressed, summary_id } = decision {
+                    discard_notification(&connection, &server, current_id, false).await;
+                    send_summary(&server, &sender, &app_name, &app_icon, suppressed, summary_id).await;
+                    return;
+                }
+            }
 
-            let notification = Notification {
+            let mut notification = Notification {
                 id: current_id,
                 app_name,
                 replaces_id,
@@ -185,22 +484,54 @@ async fn handle_method_call(
                 body,
                 actions,
                 hints,
+                parsed_hints,
                 expire_timeout,
+                received,
+                muted: false,
             };
+            let verdict = server.rules.borrow().apply(&mut notification);
+            for command in &verdict.commands {
+                run_command(command, &notification);
+            }
+            if verdict.drop {
+                discard_notification(&connection, &server, current_id, replaces).await;
+                return;
+            }
+            server
+                .active
+                .borrow_mut()
+                .insert(current_id, notification.parsed_hints.clone());
 
-            let invoc_return = glib::Variant::tuple_from_iter(&[glib::Variant::from(current_id)]);
-
-            invocation.return_value(Some(&invoc_return));
+            let event = if replaces {
+                UIEvent::Notification(NotificationEvent::Replaced(notification))
+            } else {
+                UIEvent::Notification(NotificationEvent::NewNotification(notification))
+            };
 
+            if let Err(err) = server.sender.send(event).await {
+                println!("Error sending notification: {}", err);
+            }
+        }
+        "CloseNotification" => {
+            let id = parameters.child_get::<u32>(0);
 
-            let event = UIEvent::Notification(NotificationEvent::NewNotification(
-                notification,
-            ));
+            // the spec asks for an empty error if the notification is gone
+            if !close_notification(&connection, &server, id, CloseReason::Closed) {
+                invocation.return_dbus_error("org.freedesktop.DBus.Error.Failed", "");
+                return;
+            }
+            invocation.return_value(None);
 
-            if let Err(err) = sender.send(event).await {
+            let event = UIEvent::Notification(NotificationEvent::Closed(id));
+            if let Err(err) = server.sender.send(event).await {
                 println!("Error sending notification: {}", err);
             }
         }
-        _ => {}
+        _ => {
+            invocation.return_dbus_error(
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                &format!("Unknown method {}", method_name),
+            );
+        }
     }
 }
@@ -0,0 +1,332 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use async_channel::Sender;
+use cascade::cascade;
+use glib::{self};
+use gtk::prelude::*;
+use gtk::{self, gio};
+use layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+
+use crate::dnd::DoNotDisturb;
+use crate::events::ServerEvent;
+use crate::expiry::ExpiryScheduler;
+use crate::modules::{
+    bind_notification_widget, create_notification_widget, invoke_action, keep_time_updated,
+};
+use crate::notification::NotificationObject;
+
+const SLIDE_DURATION: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    fn edges(self) -> (Edge, Edge) {
+        match self {
+            Corner::TopLeft => (Edge::Top, Edge::Left),
+            Corner::TopRight => (Edge::Top, Edge::Right),
+            Corner::BottomLeft => (Edge::Bottom, Edge::Left),
+            Corner::BottomRight => (Edge::Bottom, Edge::Right),
+        }
+    }
+    fn is_top(self) -> bool {
+        matches!(self, Corner::TopLeft | Corner::TopRight)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PopupConfig {
+    pub corner: Corner,
+    /// older popups are dismissed once there are more than this on screen
+    pub max_visible: usize,
+}
+
+impl Default for PopupConfig {
+    fn default() -> Self {
+        Self {
+            corner: Corner::TopRight,
+            max_visible: 3,
+        }
+    }
+}
+
+struct Popup {
+    notification: NotificationObject,
+    revealer: gtk::Revealer,
+    widget: gtk::Box,
+    expired_handler: glib::SignalHandlerId,
+}
+
+/// Toasts for incoming notifications, shown in their own overlay window so
+/// they are visible without opening the notification center.
+pub struct NotificationPopups {
+    window: gtk::Window,
+    container: gtk::Box,
+    config: Cell<PopupConfig>,
+    popups: RefCell<Vec<Popup>>,
+    s_server: Sender<ServerEvent>,
+    expiry: Rc<ExpiryScheduler>,
+    dnd: Rc<DoNotDisturb>,
+}
+
+impl NotificationPopups {
+    pub fn new(
+        app: &gtk::Application,
+        config: PopupConfig,
+        store: &gio::ListStore,
+        s_server: Sender<ServerEvent>,
+        expiry: Rc<ExpiryScheduler>,
+        dnd: Rc<DoNotDisturb>,
+    ) -> Rc<Self> {
+        let container = cascade! {
+            gtk::Box::new(gtk::Orientation::Vertical, 5);
+            ..set_css_classes(&["notification-popups"]);
+        };
+
+        let window = cascade! {
+            gtk::Window::new();
+            ..set_application(Some(app));
+            ..init_layer_shell();
+            ..set_layer(Layer::Overlay);
+            // inline replies need the keyboard, but only once they are clicked
+            ..set_keyboard_mode(KeyboardMode::OnDemand);
+            ..set_width_request(300);
+            ..set_css_classes(&["notification-popup-window"]);
+            ..set_child(Some(&container));
+        };
+        anchor(&window, config.corner);
+
+        let popups = Rc::new(Self {
+            window,
+            container,
+            config: Cell::new(config),
+            popups: RefCell::new(Vec::new()),
+            s_server,
+            expiry,
+            dnd,
+        });
+
+        // the popups follow the store, so anything added to the notification
+        // center pops up and anything removed from it goes away
+        store.connect_items_changed(glib::clone!(
+            #[strong] popups,
+            move |store, position, removed, added| {
+                if removed > 0 {
+                    popups.retain_existing(store);
+                }
+                for position in position..position + added {
+                    if let Some(notif) = store.item(position).and_downcast::<NotificationObject>() {
+                        popups.show(&notif);
+                    }
+                }
+            }
+        ));
+
+        popups
+    }
+
+    pub fn show(self: &Rc<Self>, notif: &NotificationObject) {
+        if notif.expired() {
+            return;
+        }
+
+        // replaced notifications update their popup in place
+        if let Some((widget, revealer)) = self.find(notif.id()) {
+            bind_notification_widget(&widget, notif, &self.s_server);
+            revealer.set_reveal_child(true);
+            return;
+        }
+
+        // with do-not-disturb on, new notifications only go into the history
+        if notif.muted() || !self.dnd.allows(notif.urgency_level()) {
+            return;
+        }
+
+        let widget = create_notification_widget();
+        bind_notification_widget(&widget, notif, &self.s_server);
+        self.connect_controllers(&widget, notif.id());
+        let weak_notif = notif.downgrade();
+        keep_time_updated(&widget, move || weak_notif.upgrade());
+
+        let revealer = cascade! {
+            gtk::Revealer::new();
+            ..set_transition_duration(SLIDE_DURATION);
+            ..set_child(Some(&widget));
+        };
+        if self.config.get().corner.is_top() {
+            revealer.set_transition_type(gtk::RevealerTransitionType::SlideDown);
+            self.container.prepend(&revealer);
+        } else {
+            revealer.set_transition_type(gtk::RevealerTransitionType::SlideUp);
+            self.container.append(&revealer);
+        }
+
+        // the popup leaves the screen once it expires, but stays in the list
+        let expired_handler = notif.connect_expired_notify(glib::clone!(
+            #[weak(rename_to = popups)] self,
+            move |notif| {
+                if notif.expired() {
+                    popups.dismiss(notif.id());
+                }
+            }
+        ));
+
+        revealer.connect_child_revealed_notify(glib::clone!(
+            #[weak(rename_to = popups)] self,
+            move |revealer| {
+                if !revealer.is_child_revealed() && !revealer.reveals_child() {
+                    popups.container.remove(revealer);
+                    popups.update_visibility();
+                }
+            }
+        ));
+
+        self.popups.borrow_mut().push(Popup {
+            notification: notif.clone(),
+            revealer: revealer.clone(),
+            widget,
+            expired_handler,
+        });
+
+        self.window.present();
+        revealer.set_reveal_child(true);
+        self.enforce_max_visible();
+    }
+
+    /// Moves the popups to the new corner, those over the new limit go away.
+    pub fn set_config(&self, config: PopupConfig) {
+        let old = self.config.replace(config);
+        if old.corner != config.corner {
+            anchor(&self.window, config.corner);
+        }
+        // the newest popup stays next to the corner
+        if old.corner.is_top() != config.corner.is_top() {
+            let transition = if config.corner.is_top() {
+                gtk::RevealerTransitionType::SlideDown
+            } else {
+                gtk::RevealerTransitionType::SlideUp
+            };
+            let mut child = self.container.first_child();
+            while let Some(widget) = child {
+                child = widget.next_sibling();
+                self.container.reorder_child_after(&widget, gtk::Widget::NONE);
+                if let Some(revealer) = widget.downcast_ref::<gtk::Revealer>() {
+                    revealer.set_transition_type(transition);
+                }
+            }
+        }
+        self.enforce_max_visible();
+    }
+
+    /// Slides the popup out, the notification itself is left alone.
+    pub fn dismiss(&self, id: u32) {
+        let popup = {
+            let mut popups = self.popups.borrow_mut();
+            let position = match popups.iter().position(|popup| popup.notification.id() == id) {
+                Some(position) => position,
+                None => return,
+            };
+            popups.remove(position)
+        };
+        popup.notification.disconnect(popup.expired_handler);
+        popup.revealer.set_reveal_child(false);
+    }
+
+    fn find(&self, id: u32) -> Option<(gtk::Box, gtk::Revealer)> {
+        self.popups
+            .borrow()
+            .iter()
+            .find(|popup| popup.notification.id() == id)
+            .map(|popup| (popup.widget.clone(), popup.revealer.clone()))
+    }
+
+    fn retain_existing(&self, store: &gio::ListStore) {
+        let gone: Vec<u32> = self
+            .popups
+            .borrow()
+            .iter()
+            .map(|popup| popup.notification.clone())
+            .filter(|notif| store.find(notif).is_none())
+            .map(|notif| notif.id())
+            .collect();
+        for id in gone {
+            self.dismiss(id);
+        }
+    }
+
+    fn enforce_max_visible(&self) {
+        let overflow: Vec<u32> = {
+            let popups = self.popups.borrow();
+            let count = popups.len().saturating_sub(self.config.get().max_visible);
+            popups[..count]
+                .iter()
+                .map(|popup| popup.notification.id())
+                .collect()
+        };
+        for id in overflow {
+            self.dismiss(id);
+        }
+    }
+
+    fn connect_controllers(self: &Rc<Self>, widget: &gtk::Box, id: u32) {
+        // like a row in the list, clicking invokes the default action
+        let click = gtk::GestureClick::new();
+        click.connect_released(glib::clone!(
+            #[weak(rename_to = popups)] self,
+            #[weak] widget,
+            move |_, _, _, _| {
+                let notif = popups
+                    .popups
+                    .borrow()
+                    .iter()
+                    .find(|popup| popup.notification.id() == id)
+                    .map(|popup| popup.notification.clone());
+                let has_default = notif.is_some_and(|notif| {
+                    !notif.expired() && notif.action_pairs().iter().any(|(key, _)| key == "default")
+                });
+                if has_default {
+                    invoke_action(&widget, &popups.s_server, id, "default");
+                }
+                // resident notifications stay open, but their popup goes away
+                popups.dismiss(id);
+            }
+        ));
+        widget.add_controller(click);
+
+        // the timer is paused while the popup is hovered
+        let hover = gtk::EventControllerMotion::new();
+        hover.connect_enter(glib::clone!(
+            #[weak(rename_to = expiry)] self.expiry,
+            move |_, _, _| {
+                expiry.pause(id);
+            }
+        ));
+        hover.connect_leave(glib::clone!(
+            #[weak(rename_to = expiry)] self.expiry,
+            move |_| {
+                expiry.resume(id);
+            }
+        ));
+        widget.add_controller(hover);
+    }
+
+    fn update_visibility(&self) {
+        self.window.set_visible(self.container.first_child().is_some());
+    }
+}
+
+fn anchor(window: &gtk::Window, corner: Corner) {
+    let (vertical, horizontal) = corner.edges();
+    for edge in [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right] {
+        let anchored = edge == vertical || edge == horizontal;
+        window.set_anchor(edge, anchored);
+        window.set_margin(edge, if anchored { 10 } else { 0 });
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+pub const DEFAULT_LIMIT: u32 = 10;
+/// in seconds
+pub const DEFAULT_WINDOW: i64 = 10;
+
+/// What to do with a notification coming from a sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// the sender is over its limit, the count includes this notification
+    Suppress { suppressed: u32, summary_id: Option<u32> },
+}
+
+#[derive(Debug)]
+struct SenderState {
+    window_start: i64,
+    count: u32,
+    suppressed: u32,
+    /// id of the "N more from X" entry shown for this window
+    summary_id: Option<u32>,
+}
+
+/// Allows every D-Bus sender a fixed number of notifications per window,
+/// everything above that is suppressed until the window is over.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: u32,
+    window: i64,
+    senders: HashMap<String, SenderState>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: i64) -> Self {
+        Self {
+            limit,
+            window,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// `now` is a unix timestamp in seconds. An empty sender is a peer on a
+    /// direct connection, those are all counted together.
+    pub fn check(&mut self, sender: &str, now: i64) -> Decision {
+        // senders that stayed quiet for a whole window are forgotten
+        let window = self.window;
+        self.senders.retain(|_, state| now - state.window_start < window);
+
+        let state = self
+            .senders
+            .entry(sender.to_string())
+            .or_insert(SenderState {
+                window_start: now,
+                count: 0,
+                suppressed: 0,
+                summary_id: None,
+            });
+
+        state.count += 1;
+        if state.count <= self.limit {
+            return Decision::Allow;
+        }
+        state.suppressed += 1;
+        Decision::Suppress {
+            suppressed: state.suppressed,
+            summary_id: state.summary_id,
+        }
+    }
+
+    /// Remembers the summary entry, so later notifications of the same window
+    /// update it instead of adding another one.
+    pub fn set_summary_id(&mut self, sender: &str, id: u32) {
+        if let Some(state) = self.senders.get_mut(sender) {
+            state.summary_id = Some(id);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use glib::{self};
+use gtk::gio;
+use gtk::prelude::*;
+
+/// Editors tend to save in several steps, changes are only reported once the
+/// file was quiet for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Calls `callback` whenever the file is changed, created or removed. The
+/// file is watched for as long as the returned monitor is alive.
+pub fn watch_file<F>(path: &Path, callback: F) -> Option<gio::FileMonitor>
+where
+    F: Fn() + 'static,
+{
+    let file = gio::File::for_path(path);
+    let monitor = match file.monitor_file(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE) {
+        Ok(monitor) => monitor,
+        Err(err) => {
+            println!("Error watching {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    let callback = Rc::new(callback);
+    let pending = Rc::new(Cell::new(false));
+    monitor.connect_changed(move |_, _, _, event| {
+        let relevant = matches!(
+            event,
+            gio::FileMonitorEvent::ChangesDoneHint
+                | gio::FileMonitorEvent::Created
+                | gio::FileMonitorEvent::Deleted
+                | gio::FileMonitorEvent::Renamed
+                | gio::FileMonitorEvent::MovedIn
+                | gio::FileMonitorEvent::MovedOut
+        );
+        if !relevant || pending.replace(true) {
+            return;
+        }
+        glib::timeout_add_local_once(
+            SETTLE_TIME,
+            glib::clone!(
+                #[strong] pending,
+                #[strong] callback,
+                move || {
+                    pending.set(false);
+                    callback();
+                }
+            ),
+        );
+    });
+    Some(monitor)
+}
@@ -0,0 +1,355 @@
+use glib::{self};
+use serde::Deserialize;
+
+use crate::hints::Urgency;
+use crate::notification_server::Notification;
+
+/// What a rule does with the notifications it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    Drop,
+    /// keeps the notification in the history, but out of the popups
+    MutePopup,
+    SetUrgency(Urgency),
+    SetTimeout(i32),
+    SetSummary(String),
+    /// runs through `sh -c`, with the notification in the environment
+    Exec(String),
+}
+
+/// A rule matches if every condition it has is met, missing conditions match
+/// anything. `summary` and `body` are regular expressions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "RuleConfig")]
+pub struct Rule {
+    pub app_name: Option<String>,
+    pub summary: Option<String>,
+    pub body: Option<String>,
+    pub category: Option<String>,
+    pub urgency: Option<Urgency>,
+    pub actions: Vec<RuleAction>,
+}
+
+/// The result of running the rules over a notification.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Verdict {
+    pub drop: bool,
+    pub commands: Vec<String>,
+}
+
+/// Rules are the `[[rules]]` of `config.toml`:
+///
+/// ```toml
+/// [[rules]]
+/// app-name = "buildbot"
+/// summary = "^Build (succeeded|started)"
+/// mute-popup = true
+/// set-timeout = 2000
+///
+/// [[rules]]
+/// category = "x-pager.alert"
+/// set-urgency = "critical"
+/// exec = "paplay /usr/share/sounds/freedesktop/stereo/alarm-clock-elapsed.oga"
+/// ```
+///
+/// Every matching rule is applied, in the order they appear in the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+/// A rule as it is written in the config, checked while it is read.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleConfig {
+    app_name: Option<String>,
+    summary: Option<String>,
+    body: Option<String>,
+    category: Option<String>,
+    urgency: Option<String>,
+    drop: bool,
+    mute_popup: bool,
+    set_urgency: Option<String>,
+    set_timeout: Option<i32>,
+    set_summary: Option<String>,
+    exec: Option<String>,
+}
+
+impl Rules {
+    /// Applies every matching rule to the notification. Conditions are checked
+    /// against the notification as it is changed by earlier rules.
+    pub fn apply(&self, n: &mut Notification) -> Verdict {
+        let mut verdict = Verdict::default();
+        for rule in self.rules.iter().filter(|rule| rule.matches(n)) {
+            for action in &rule.actions {
+                match action {
+                    RuleAction::Drop => verdict.drop = true,
+                    RuleAction::MutePopup => n.muted = true,
+                    RuleAction::SetUrgency(urgency) => set_urgency(n, *urgency),
+                    RuleAction::SetTimeout(timeout) => n.expire_timeout = *timeout,
+                    RuleAction::SetSummary(summary) => n.summary = summary.clone(),
+                    RuleAction::Exec(command) => verdict.commands.push(command.clone()),
+                }
+            }
+        }
+        verdict
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, n: &Notification) -> bool {
+        let category = n.parsed_hints.category.as_deref().unwrap_or_default();
+
+        self.app_name.as_ref().is_none_or(|app_name| *app_name == n.app_name)
+            && self.category.as_ref().is_none_or(|expected| expected == category)
+            && self.urgency.is_none_or(|urgency| urgency == n.parsed_hints.urgency)
+            && self.summary.as_ref().is_none_or(|pattern| regex_matches(pattern, &n.summary))
+            && self.body.as_ref().is_none_or(|pattern| regex_matches(pattern, &n.body))
+    }
+}
+
+fn regex_matches(pattern: &str, text: &str) -> bool {
+    glib::Regex::match_simple(
+        pattern,
+        text,
+        glib::RegexCompileFlags::DEFAULT,
+        glib::RegexMatchFlags::DEFAULT,
+    )
+}
+
+/// The raw hints are updated as well, they are what ends up in the history.
+fn set_urgency(n: &mut Notification, urgency: Urgency) {
+    let hints = glib::VariantDict::new(Some(&n.hints));
+    hints.insert("urgency", urgency as u8);
+    n.hints = hints.end();
+    n.parsed_hints.urgency = urgency;
+}
+
+impl TryFrom<RuleConfig> for Rule {
+    type Error = String;
+
+    fn try_from(config: RuleConfig) -> Result<Self, Self::Error> {
+        let urgency = |key: &str, value: Option<String>| -> Result<Option<Urgency>, String> {
+            value
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid value for {}: {}", key, value))
+                })
+                .transpose()
+        };
+        // bad patterns are reported now, instead of silently never matching
+        let pattern = |value: Option<String>| -> Result<Option<String>, String> {
+            if let Some(pattern) = &value {
+                glib::Regex::new(
+                    pattern,
+                    glib::RegexCompileFlags::DEFAULT,
+                    glib::RegexMatchFlags::DEFAULT,
+                )
+                .map_err(|err| err.to_string())?;
+            }
+            Ok(value)
+        };
+
+        let mut actions = Vec::new();
+        if config.drop {
+            actions.push(RuleAction::Drop);
+        }
+        if config.mute_popup {
+            actions.push(RuleAction::MutePopup);
+        }
+        if let Some(urgency) = urgency("set-urgency", config.set_urgency)? {
+            actions.push(RuleAction::SetUrgency(urgency));
+        }
+        if let Some(timeout) = config.set_timeout {
+            actions.push(RuleAction::SetTimeout(timeout));
+        }
+        if let Some(summary) = config.set_summary {
+            actions.push(RuleAction::SetSummary(summary));
+        }
+        if let Some(command) = config.exec {
+            actions.push(RuleAction::Exec(command));
+        }
+
+        Ok(Rule {
+            app_name: config.app_name,
+            summary: pattern(config.summary)?,
+            body: pattern(config.body)?,
+            category: config.category,
+            urgency: urgency("urgency", config.urgency)?,
+            actions,
+        })
+    }
+}
+
+/// Environment handed to `exec` commands.
+pub fn command_env(n: &Notification) -> Vec<(&'static str, String)> {
+    vec![
+        ("NOTIFY_ID", n.id.to_string()),
+        ("NOTIFY_APP_NAME", n.app_name.clone()),
+        ("NOTIFY_SUMMARY", n.summary.clone()),
+        ("NOTIFY_BODY", n.body.clone()),
+        ("NOTIFY_URGENCY", n.parsed_hints.urgency.css_class().to_string()),
+        ("NOTIFY_HINTS", n.hints.print(false).to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hints::Hints;
+
+    /// The rules the way they are read from `config.toml`.
+    fn rules(data: &str) -> Result<Rules, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            rules: Rules,
+        }
+        toml::from_str::<Config>(data).map(|config| config.rules)
+    }
+
+    fn notification(app_name: &str, summary: &str, body: &str) -> Notification {
+        Notification {
+            id: 1,
+            app_name: app_name.to_string(),
+            replaces_id: 0,
+            app_icon: String::new(),
+            summary: summary.to_string(),
+            body: body.to_string(),
+            actions: Vec::new(),
+            hints: glib::VariantDict::new(None).end(),
+            parsed_hints: Hints::default(),
+            expire_timeout: -1,
+            received: 0,
+            muted: false,
+        }
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert!(rules("[[rules]]\nurgency = \"loud\"\n").is_err());
+        assert!(rules("[[rules]]\nset-urgency = \"loud\"\n").is_err());
+        assert!(rules("[[rules]]\nsummary = \"(unclosed\"\n").is_err());
+        assert!(rules("[[rules]]\nbody = \"[a-\"\n").is_err());
+        assert!(rules("[[rules]]\nset-timeout = \"soon\"\n").is_err());
+        assert!(rules("[[rules]]\nsummmary = \"typo\"\n").is_err());
+    }
+
+    #[test]
+    fn conditions_and_actions_are_read() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            app-name = "mail"
+            summary = "^New"
+            category = "email.arrived"
+            urgency = "low"
+            drop = true
+            mute-popup = true
+            set-urgency = "critical"
+            set-timeout = 2000
+            set-summary = "Mail"
+            exec = "true"
+            "#,
+        )
+        .unwrap();
+        let rule = &rules.rules[0];
+        assert_eq!(rule.app_name.as_deref(), Some("mail"));
+        assert_eq!(rule.summary.as_deref(), Some("^New"));
+        assert_eq!(rule.body, None);
+        assert_eq!(rule.category.as_deref(), Some("email.arrived"));
+        assert_eq!(rule.urgency, Some(Urgency::Low));
+        assert_eq!(
+            rule.actions,
+            vec![
+                RuleAction::Drop,
+                RuleAction::MutePopup,
+                RuleAction::SetUrgency(Urgency::Critical),
+                RuleAction::SetTimeout(2000),
+                RuleAction::SetSummary("Mail".to_string()),
+                RuleAction::Exec("true".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rule_matches_every_condition() {
+        let rule = Rule {
+            app_name: Some("mail".to_string()),
+            summary: Some("^New".to_string()),
+            body: Some("invoice".to_string()),
+            ..Default::default()
+        };
+        assert!(rule.matches(&notification("mail", "New message", "your invoice")));
+        assert!(!rule.matches(&notification("mailer", "New message", "your invoice")));
+        assert!(!rule.matches(&notification("mail", "Re: New message", "your invoice")));
+        assert!(!rule.matches(&notification("mail", "New message", "hello")));
+
+        let mut n = notification("mail", "", "");
+        assert!(Rule::default().matches(&n));
+        let rule = Rule {
+            category: Some("email.arrived".to_string()),
+            urgency: Some(Urgency::Critical),
+            ..Default::default()
+        };
+        assert!(!rule.matches(&n));
+        n.parsed_hints.category = Some("email.arrived".to_string());
+        assert!(!rule.matches(&n));
+        n.parsed_hints.urgency = Urgency::Critical;
+        assert!(rule.matches(&n));
+    }
+
+    #[test]
+    fn later_rules_see_earlier_rewrites() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            summary = "^Renamed"
+            set-timeout = 1
+
+            [[rules]]
+            summary = "^Original"
+            set-summary = "Renamed"
+
+            [[rules]]
+            summary = "^Renamed"
+            set-timeout = 2
+            "#,
+        )
+        .unwrap();
+        let mut n = notification("app", "Original", "");
+        let verdict = rules.apply(&mut n);
+        assert_eq!(verdict, Verdict::default());
+        assert_eq!(n.summary, "Renamed");
+        assert_eq!(n.expire_timeout, 2);
+    }
+
+    #[test]
+    fn dropped_notifications_still_run_commands() {
+        let rules = rules(
+            "[[rules]]\ndrop = true\nexec = \"first\"\n[[rules]]\nexec = \"second\"\n",
+        )
+        .unwrap();
+        let mut n = notification("app", "", "");
+        assert_eq!(
+            rules.apply(&mut n),
+            Verdict {
+                drop: true,
+                commands: vec!["first".to_string(), "second".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn set_urgency_updates_the_raw_hints() {
+        let rules = rules("[[rules]]\nset-urgency = \"critical\"\nmute-popup = true\n").unwrap();
+        let mut n = notification("app", "", "");
+        rules.apply(&mut n);
+        assert!(n.muted);
+        assert_eq!(n.parsed_hints.urgency, Urgency::Critical);
+        let hints = glib::VariantDict::new(Some(&n.hints));
+        assert_eq!(hints.lookup::<u8>("urgency").unwrap(), Some(Urgency::Critical as u8));
+        assert_eq!(Hints::parse(&hints).urgency, Urgency::Critical);
+    }
+}
@@ -0,0 +1,158 @@
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+
+use glib::{self};
+use gtk::prelude::*;
+
+use crate::hints::{Hints, Urgency};
+use crate::utils::unwrap_or_return;
+
+/// The theme every other sound theme falls back to.
+const FALLBACK_THEME: &str = "freedesktop";
+const SOUND_EXTENSIONS: &[&str] = &["oga", "ogg", "wav"];
+const STATE_FILE: &str = "sound.ini";
+const STATE_GROUP: &str = "sound";
+
+#[derive(Debug, Clone)]
+pub struct SoundConfig {
+    /// only used until the mute toggle is switched for the first time, from
+    /// then on the saved state applies
+    pub muted: bool,
+    /// sound names played when the client does not ask for a sound, indexed
+    /// by urgency
+    pub defaults: [Option<String>; 3],
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        Self {
+            muted: false,
+            defaults: [None, None, Some("dialog-warning".to_string())],
+        }
+    }
+}
+
+/// Plays the sound of incoming notifications, either the file from the
+/// `sound-file` hint or a name from the sound theme.
+pub struct SoundPlayer {
+    muted: Cell<bool>,
+    config: RefCell<SoundConfig>,
+    path: PathBuf,
+    // kept around until the next sound, dropping it would stop playback
+    playing: RefCell<Option<gtk::MediaFile>>,
+}
+
+impl SoundPlayer {
+    pub fn new(config: SoundConfig) -> Self {
+        let player = Self {
+            muted: Cell::new(false),
+            config: RefCell::new(SoundConfig::default()),
+            path: glib::user_state_dir().join(crate::NAME).join(STATE_FILE),
+            playing: RefCell::new(None),
+        };
+        player.set_config(config);
+        player
+    }
+
+    pub fn set_config(&self, config: SoundConfig) {
+        self.muted.set(self.load().unwrap_or(config.muted));
+        self.config.replace(config);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted.get()
+    }
+
+    /// The choice is saved, so it survives restarts.
+    pub fn set_muted(&self, muted: bool) {
+        if self.muted.replace(muted) != muted {
+            self.save();
+        }
+        if muted {
+            self.stop();
+        }
+    }
+
+    /// Does nothing if sounds are muted or the notification asks for silence.
+    pub fn play(&self, hints: &Hints) {
+        if self.muted.get() || hints.suppress_sound {
+            return;
+        }
+        let path = match (&hints.sound_file, &hints.sound_name) {
+            (Some(file), _) => Some(PathBuf::from(file)),
+            (None, Some(name)) => lookup_sound(name),
+            (None, None) => self.default_sound(hints.urgency).as_deref().and_then(lookup_sound),
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+
+        self.stop();
+        let media = gtk::MediaFile::for_filename(&path);
+        media.connect_error_notify(|media| {
+            if let Some(err) = media.error() {
+                println!("Error playing sound: {}", err);
+            }
+        });
+        media.play();
+        self.playing.replace(Some(media));
+    }
+
+    fn stop(&self) {
+        if let Some(media) = self.playing.take() {
+            media.pause();
+        }
+    }
+
+    fn default_sound(&self, urgency: Urgency) -> Option<String> {
+        self.config.borrow().defaults[urgency as usize].clone()
+    }
+
+    /// `None` if the mute toggle was never used.
+    fn load(&self) -> Option<bool> {
+        let file = glib::KeyFile::new();
+        file.load_from_file(&self.path, glib::KeyFileFlags::NONE).ok()?;
+        file.boolean(STATE_GROUP, "muted").ok()
+    }
+
+    fn save(&self) {
+        let file = glib::KeyFile::new();
+        file.set_boolean(STATE_GROUP, "muted", self.muted.get());
+
+        if let Some(dir) = self.path.parent() {
+            unwrap_or_return!(std::fs::create_dir_all(dir), Result);
+        }
+        unwrap_or_return!(file.save_to_file(&self.path), Result);
+    }
+}
+
+/// Looks the name up in the current sound theme, like the sound theme spec
+/// describes it.
+fn lookup_sound(name: &str) -> Option<PathBuf> {
+    let theme = gtk::Settings::default()
+        .and_then(|settings| settings.gtk_sound_theme_name())
+        .map(|theme| theme.to_string())
+        .unwrap_or_else(|| FALLBACK_THEME.to_string());
+
+    let mut data_dirs = vec![glib::user_data_dir()];
+    data_dirs.extend(glib::system_data_dirs());
+
+    // "message-new-instant" falls back to "message-new", then to "message"
+    let mut names = std::iter::successors(Some(name), |name| {
+        name.rsplit_once('-').map(|(name, _)| name)
+    });
+    names.find_map(|name| {
+        [theme.as_str(), FALLBACK_THEME]
+            .iter()
+            .flat_map(|theme| data_dirs.iter().map(move |dir| dir.join("sounds").join(theme)))
+            .find_map(|theme_dir| find_in_theme(&theme_dir, name))
+    })
+}
+
+fn find_in_theme(theme_dir: &Path, name: &str) -> Option<PathBuf> {
+    SOUND_EXTENSIONS
+        .iter()
+        .map(|extension| theme_dir.join("stereo").join(format!("{}.{}", name, extension)))
+        .find(|path| path.exists())
+}
@@ -0,0 +1,101 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use glib::{self};
+use gtk::{self, gdk};
+
+const USER_STYLE_FILE: &str = "style.css";
+
+/// The built-in stylesheet with the user's `style.css` on top of it. The user
+/// stylesheet only needs to contain what it changes.
+///
+/// These style classes are kept stable for user stylesheets:
+///
+/// - `bar`: the bar window, together with `top`, `bottom`, `left` or `right`
+/// - `module`: the button of every module in the bar
+/// - `date-display`: the time module
+/// - `notification-list`: the list of the notifications module
+/// - `notification-header`: the buttons above that list
+/// - `notification-group`: the header of an app's notifications, with a
+///   `count` label
+/// - `name-lost`: the warning shown when another server took over
+/// - `notification`: a single notification, together with `low`, `normal`
+///   or `critical`
+/// - `header`, `summary`, `time`, `body`, `image`, `actions` and `reply`: the
+///   parts of a notification
+/// - `close`: the close button of a notification
+/// - `notification-popup-window` and `notification-popups`: the popup window
+///   and the box holding the popups
+pub struct Style {
+    builtin: gtk::CssProvider,
+    user: RefCell<Option<gtk::CssProvider>>,
+}
+
+impl Style {
+    pub fn new() -> Self {
+        Self {
+            builtin: gtk::CssProvider::new(),
+            user: RefCell::new(None),
+        }
+    }
+
+    pub fn path() -> PathBuf {
+        glib::user_config_dir().join(crate::NAME).join(USER_STYLE_FILE)
+    }
+
+    /// Installs the built-in stylesheet and the user stylesheet.
+    pub fn load(&self) {
+        let display = gdk::Display::default().expect("Could not connect to a display.");
+        self.builtin.connect_parsing_error(|_, section, err| {
+            report_error("built-in style.css", section, err);
+        });
+        self.builtin.load_from_string(include_str!("style.css"));
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &self.builtin,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+        );
+        self.load_user();
+    }
+
+    /// Installs a fresh provider in place of the current user stylesheet, so
+    /// edits show up without a restart.
+    pub fn load_user(&self) {
+        let display = gdk::Display::default().expect("Could not connect to a display.");
+        if let Some(old) = self.user.take() {
+            gtk::style_context_remove_provider_for_display(&display, &old);
+        }
+
+        let path = Self::path();
+        let css = match std::fs::read_to_string(&path) {
+            Ok(css) => css,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => {
+                println!("Error loading {}: {}", path.display(), err);
+                return;
+            }
+        };
+
+        let provider = gtk::CssProvider::new();
+        let name = path.display().to_string();
+        provider.connect_parsing_error(move |_, section, err| report_error(&name, section, err));
+        provider.load_from_string(&css);
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &provider,
+            gtk::STYLE_PROVIDER_PRIORITY_USER,
+        );
+        self.user.replace(Some(provider));
+    }
+}
+
+fn report_error(file: &str, section: &gtk::CssSection, err: &glib::Error) {
+    let location = section.start_location();
+    println!(
+        "Error in {}:{}:{}: {}",
+        file,
+        location.lines() + 1,
+        location.line_chars() + 1,
+        err
+    );
+}
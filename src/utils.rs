@@ -27,6 +27,7 @@ pub fn set_interval<T: FnMut() -> glib::ControlFlow + 'static>(
     })
 }
 
+#[macro_export]
 macro_rules! unwrap_or_return {
 
     ($expression:expr, Result) => {
@@ -48,4 +49,4 @@ macro_rules! unwrap_or_return {
     };
 }
 
-pub(crate) use unwrap_or_return;
\ No newline at end of file
+pub use crate::unwrap_or_return;
\ No newline at end of file
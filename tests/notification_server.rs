@@ -0,0 +1,358 @@
+//! Runs the notification server on a private `dbus-daemon`, without gtk or a
+//! display, and talks to it the way a client would.
+use std::future::Future;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use async_channel::{Receiver, Sender};
+use glib::{self, ToVariant};
+
+use panel::events::{NotificationEvent, ServerEvent, UIEvent};
+use panel::notification_server::{
+    CloseReason, NotificationServer, NOTIFICATION_DBUS_INTERFACE, NOTIFICATION_DBUS_NAME,
+    NOTIFICATION_DBUS_PATH,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A session bus that only lives as long as the test.
+struct PrivateBus {
+    daemon: Child,
+    address: String,
+}
+
+impl PrivateBus {
+    fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon is needed to run these tests");
+
+        let stdout = daemon.stdout.take().expect("dbus-daemon has no stdout");
+        let mut address = String::new();
+        BufReader::new(stdout)
+            .read_line(&mut address)
+            .expect("dbus-daemon did not print its address");
+
+        Self {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    fn connect(&self) -> gio::DBusConnection {
+        gio::DBusConnection::for_address_sync(
+            &self.address,
+            gio::DBusConnectionFlags::AUTHENTICATION_CLIENT
+                | gio::DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+            None,
+            gio::Cancellable::NONE,
+        )
+        .expect("could not connect to the private bus")
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// A server on its own bus, a client connection to it and both ends of the
+/// channels the ui would use.
+struct Harness {
+    client: gio::DBusConnection,
+    r_ui: Receiver<UIEvent>,
+    s_server: Sender<ServerEvent>,
+    signals: Receiver<(String, glib::Variant)>,
+    _bus: PrivateBus,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        let bus = PrivateBus::start();
+        let (s_ui, r_ui) = async_channel::unbounded();
+        let (s_server, r_server) = async_channel::unbounded();
+
+        let server = NotificationServer::new(s_ui, r_server);
+        server.own_name_on_connection(&bus.connect());
+        match timeout(r_ui.recv()).await {
+            Ok(UIEvent::NameOwned(true)) => {}
+            event => panic!("expected the name to be acquired, got {:?}", event),
+        }
+
+        let client = bus.connect();
+        let (s_signal, signals) = async_channel::unbounded();
+        client.signal_subscribe(
+            None,
+            Some(NOTIFICATION_DBUS_INTERFACE),
+            None,
+            Some(NOTIFICATION_DBUS_PATH),
+            None,
+            gio::DBusSignalFlags::NONE,
+            move |_, _, _, _, signal, params| {
+                let _ = s_signal.try_send((signal.to_string(), params.clone()));
+            },
+        );
+
+        Self {
+            client,
+            r_ui,
+            s_server,
+            signals,
+            _bus: bus,
+        }
+    }
+
+    async fn call(&self, method: &str, params: Option<glib::Variant>) -> glib::Variant {
+        let reply = self.client.call_future(
+            Some(NOTIFICATION_DBUS_NAME),
+            NOTIFICATION_DBUS_PATH,
+            NOTIFICATION_DBUS_INTERFACE,
+            method,
+            params.as_ref(),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+        );
+        timeout(reply)
+            .await
+            .unwrap_or_else(|err| panic!("{} failed: {}", method, err))
+    }
+
+    async fn notify(&self, replaces_id: u32, summary: &str, actions: &[&str]) -> u32 {
+        let actions: Vec<String> = actions.iter().map(|action| action.to_string()).collect();
+        let params = glib::Variant::tuple_from_iter([
+            "test".to_variant(),
+            replaces_id.to_variant(),
+            "".to_variant(),
+            summary.to_variant(),
+            "body".to_variant(),
+            actions.to_variant(),
+            glib::VariantDict::new(None).end(),
+            (-1i32).to_variant(),
+        ]);
+        let (id,) = self
+            .call("Notify", Some(params))
+            .await
+            .get::<(u32,)>()
+            .expect("Notify returned the wrong type");
+        id
+    }
+
+    async fn ui_event(&self) -> UIEvent {
+        timeout(self.r_ui.recv())
+            .await
+            .expect("the ui channel was closed")
+    }
+
+    async fn signal(&self) -> (String, glib::Variant) {
+        timeout(self.signals.recv())
+            .await
+            .expect("the signal channel was closed")
+    }
+}
+
+async fn timeout<T>(future: impl Future<Output = T>) -> T {
+    glib::future_with_timeout(TIMEOUT, future)
+        .await
+        .expect("timed out waiting for the server")
+}
+
+/// Runs the test on its own main context, the way the server would run in a
+/// plain `glib::MainLoop`.
+fn run<F: Future<Output = ()>>(test: impl FnOnce() -> F) {
+    let context = glib::MainContext::new();
+    context
+        .with_thread_default(|| context.block_on(test()))
+        .expect("could not acquire the main context");
+}
+
+#[test]
+fn server_information() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let info = harness.call("GetServerInformation", None).await;
+        let (name, vendor, _version, spec_version) = info
+            .get::<(String, String, String, String)>()
+            .expect("GetServerInformation returned the wrong type");
+
+        assert_eq!(name, env!("CARGO_PKG_NAME"));
+        assert_eq!(vendor, "bodenlosus");
+        assert_eq!(spec_version, "1.2");
+    });
+}
+
+#[test]
+fn capabilities() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let (capabilities,) = harness
+            .call("GetCapabilities", None)
+            .await
+            .get::<(Vec<String>,)>()
+            .expect("GetCapabilities returned the wrong type");
+
+        for capability in ["actions", "body", "body-markup", "persistence"] {
+            assert!(capabilities.iter().any(|c| c == capability), "missing {}", capability);
+        }
+        assert!(!capabilities.iter().any(|c| c == "body-images"));
+    });
+}
+
+#[test]
+fn notify_hands_out_new_ids() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let first = harness.notify(0, "first", &[]).await;
+        let second = harness.notify(0, "second", &[]).await;
+        assert_ne!(first, 0);
+        assert!(second > first);
+
+        for (id, summary) in [(first, "first"), (second, "second")] {
+            match harness.ui_event().await {
+                UIEvent::Notification(NotificationEvent::NewNotification(n)) => {
+                    assert_eq!(n.id, id);
+                    assert_eq!(n.app_name, "test");
+                    assert_eq!(n.summary, summary);
+                }
+                event => panic!("expected a new notification, got {:?}", event),
+            }
+        }
+    });
+}
+
+#[test]
+fn notify_replaces_known_ids_only() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let id = harness.notify(0, "original", &[]).await;
+        harness.ui_event().await;
+
+        assert_eq!(harness.notify(id, "replacement", &[]).await, id);
+        match harness.ui_event().await {
+            UIEvent::Notification(NotificationEvent::Replaced(n)) => {
+                assert_eq!(n.id, id);
+                assert_eq!(n.summary, "replacement");
+            }
+            event => panic!("expected a replaced notification, got {:?}", event),
+        }
+
+        // an id the server never handed out is treated like a new notification
+        let unknown = harness.notify(9999, "unknown", &[]).await;
+        assert_ne!(unknown, 9999);
+        assert!(matches!(
+            harness.ui_event().await,
+            UIEvent::Notification(NotificationEvent::NewNotification(_))
+        ));
+    });
+}
+
+#[test]
+fn close_notification() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let id = harness.notify(0, "closed", &[]).await;
+        harness.ui_event().await;
+
+        harness
+            .call("CloseNotification", Some((id,).to_variant()))
+            .await;
+
+        let (signal, params) = harness.signal().await;
+        assert_eq!(signal, "NotificationClosed");
+        assert_eq!(params.get::<(u32, u32)>(), Some((id, CloseReason::Closed as u32)));
+        assert!(matches!(
+            harness.ui_event().await,
+            UIEvent::Notification(NotificationEvent::Closed(closed)) if closed == id
+        ));
+    });
+}
+
+#[test]
+fn closing_an_unknown_notification_fails() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let reply = harness.client.call_future(
+            Some(NOTIFICATION_DBUS_NAME),
+            NOTIFICATION_DBUS_PATH,
+            NOTIFICATION_DBUS_INTERFACE,
+            "CloseNotification",
+            Some(&(42u32,).to_variant()),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+        );
+        assert!(timeout(reply).await.is_err());
+    });
+}
+
+#[test]
+fn invoked_action_closes_notification() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let id = harness.notify(0, "action", &["default", "Open"]).await;
+        harness.ui_event().await;
+
+        harness
+            .s_server
+            .send(ServerEvent::ActionInvoked(id, "default".to_string(), None))
+            .await
+            .expect("the server channel was closed");
+
+        let (signal, params) = harness.signal().await;
+        assert_eq!(signal, "ActionInvoked");
+        assert_eq!(params.get::<(u32, String)>(), Some((id, "default".to_string())));
+
+        let (signal, params) = harness.signal().await;
+        assert_eq!(signal, "NotificationClosed");
+        assert_eq!(params.get::<(u32, u32)>(), Some((id, CloseReason::Dismissed as u32)));
+        assert!(matches!(
+            harness.ui_event().await,
+            UIEvent::Notification(NotificationEvent::Closed(closed)) if closed == id
+        ));
+    });
+}
+
+#[test]
+fn expiry_from_the_ui_is_signalled() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let id = harness.notify(0, "expired", &[]).await;
+        harness.ui_event().await;
+
+        harness
+            .s_server
+            .send(ServerEvent::NotificationClosed(id, CloseReason::Expired))
+            .await
+            .expect("the server channel was closed");
+
+        let (signal, params) = harness.signal().await;
+        assert_eq!(signal, "NotificationClosed");
+        assert_eq!(params.get::<(u32, u32)>(), Some((id, CloseReason::Expired as u32)));
+    });
+}
+
+#[test]
+fn replacements_are_not_rate_limited() {
+    run(|| async {
+        let harness = Harness::start().await;
+        let id = harness.notify(0, "progress", &[]).await;
+        harness.ui_event().await;
+
+        for step in 0..20 {
+            let summary = format!("progress {}", step);
+            assert_eq!(harness.notify(id, &summary, &[]).await, id);
+            match harness.ui_event().await {
+                UIEvent::Notification(NotificationEvent::Replaced(n)) => {
+                    assert_eq!(n.id, id);
+                    assert_eq!(n.summary, summary);
+                }
+                event => panic!("expected a replaced notification, got {:?}", event),
+            }
+        }
+    });
+}